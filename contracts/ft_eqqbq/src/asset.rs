@@ -0,0 +1,36 @@
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_sdk::json_types::U128;
+use near_sdk::{near, AccountId, Gas, NearToken, Promise};
+
+const ONE_YOCTO: NearToken = NearToken::from_yoctonear(1);
+const GAS_FOR_FT_TRANSFER: Gas = Gas::from_tgas(10);
+
+/// Activo bloqueado en un depósito: NEAR nativo o un token NEP-141.
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Asset {
+    Near,
+    Ft { token_id: AccountId },
+}
+
+impl Asset {
+    /// Gas que se adjunta a la transferencia de `transfer`.
+    pub fn transfer_gas(&self) -> Gas {
+        match self {
+            Asset::Near => Gas::from_gas(0),
+            Asset::Ft { .. } => GAS_FOR_FT_TRANSFER,
+        }
+    }
+
+    /// Transfiere `amount` del activo a `receiver_id`.
+    pub fn transfer(&self, receiver_id: AccountId, amount: U128) -> Promise {
+        match self {
+            Asset::Near => Promise::new(receiver_id).transfer(NearToken::from_yoctonear(amount.0)),
+            Asset::Ft { token_id } => ext_ft_core::ext(token_id.clone())
+                .with_attached_deposit(ONE_YOCTO)
+                .with_static_gas(GAS_FOR_FT_TRANSFER)
+                .ft_transfer(receiver_id, amount, None),
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use near_sdk::json_types::U128;
+use near_sdk::{env, near};
+
+use crate::timelocks::NANOS_PER_SECOND;
+
+/// Subasta holandesa de una orden firmada, como la de 1inch Fusion. El importe
+/// que tiene que entregar el taker en la cadena de destino baja desde
+/// `start_amount` hasta el `dst_amount` de la orden a lo largo de `duration`,
+/// en línea recta entre cada par de puntos consecutivos. Como la orden se
+/// rellena entera, el importe equivale a la tasa de la subasta.
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuctionDetails {
+    /// Segundos desde epoch en que empieza a bajar el importe
+    pub start_time: u64,
+    /// Segundos desde `start_time` hasta llegar al `dst_amount` de la orden
+    pub duration: u32,
+    pub start_amount: U128,
+    /// Puntos intermedios de la curva, ordenados por `delay`
+    #[serde(default)]
+    pub points: Vec<AuctionPoint>,
+}
+
+/// Importe de la curva a los `delay` segundos de `start_time`.
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AuctionPoint {
+    pub delay: u32,
+    pub amount: U128,
+}
+
+/// Máximo de puntos intermedios de una subasta
+pub const MAX_AUCTION_POINTS: usize = 8;
+
+impl AuctionDetails {
+    /// La curva tiene que empezar después de 0 s, acabar antes de `duration` y
+    /// no subir nunca, terminando en `end_amount`.
+    pub fn validate(&self, end_amount: u128) -> Result<(), &'static str> {
+        if self.duration == 0 {
+            return Err("La subasta debe durar al menos un segundo");
+        }
+        if self.points.len() > MAX_AUCTION_POINTS {
+            return Err("La subasta tiene demasiados puntos");
+        }
+        let mut previous = AuctionPoint { delay: 0, amount: self.start_amount };
+        for point in self.points.iter().chain(&[AuctionPoint { delay: self.duration, amount: U128(end_amount) }]) {
+            if point.delay <= previous.delay {
+                return Err("Los puntos de la subasta deben estar ordenados dentro de su duración");
+            }
+            if point.amount.0 > previous.amount.0 {
+                return Err("El importe de la subasta no puede subir");
+            }
+            previous = *point;
+        }
+        Ok(())
+    }
+
+    /// Importe mínimo que tiene que ofrecer el taker en `now` (segundos desde
+    /// epoch). Entre dos puntos se redondea hacia arriba, a favor del maker.
+    pub fn amount_at(&self, end_amount: u128, now: u64) -> u128 {
+        if now <= self.start_time {
+            return self.start_amount.0;
+        }
+        let elapsed = now - self.start_time;
+        if elapsed >= u64::from(self.duration) {
+            return end_amount;
+        }
+
+        let mut previous = AuctionPoint { delay: 0, amount: self.start_amount };
+        for point in self.points.iter().chain(&[AuctionPoint { delay: self.duration, amount: U128(end_amount) }]) {
+            let delay = u64::from(point.delay);
+            if elapsed < delay {
+                let span = delay - u64::from(previous.delay);
+                let remaining = delay - elapsed;
+                let drop = previous.amount.0 - point.amount.0;
+                return point.amount.0 + mul_div_ceil(drop, remaining, span);
+            }
+            previous = *point;
+        }
+        end_amount
+    }
+
+    pub fn current_amount(&self, end_amount: u128) -> u128 {
+        self.amount_at(end_amount, env::block_timestamp() / NANOS_PER_SECOND)
+    }
+}
+
+/// `value * numerator / denominator` redondeando hacia arriba, con
+/// `numerator <= denominator` y ambos menores que 2^32, sin desbordar.
+fn mul_div_ceil(value: u128, numerator: u64, denominator: u64) -> u128 {
+    let (numerator, denominator) = (u128::from(numerator), u128::from(denominator));
+    value / denominator * numerator + (value % denominator * numerator).div_ceil(denominator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: u64 = 1_000;
+
+    fn auction(start_amount: u128, duration: u32, points: &[(u32, u128)]) -> AuctionDetails {
+        AuctionDetails {
+            start_time: START,
+            duration,
+            start_amount: U128(start_amount),
+            points: points.iter().map(|&(delay, amount)| AuctionPoint { delay, amount: U128(amount) }).collect(),
+        }
+    }
+
+    #[test]
+    fn linear_curve() {
+        let linear = auction(2_000, 100, &[]);
+        assert_eq!(linear.amount_at(1_000, 0), 2_000);
+        assert_eq!(linear.amount_at(1_000, START), 2_000);
+        assert_eq!(linear.amount_at(1_000, START + 1), 1_990);
+        assert_eq!(linear.amount_at(1_000, START + 25), 1_750);
+        assert_eq!(linear.amount_at(1_000, START + 50), 1_500);
+        assert_eq!(linear.amount_at(1_000, START + 99), 1_010);
+        assert_eq!(linear.amount_at(1_000, START + 100), 1_000);
+        assert_eq!(linear.amount_at(1_000, u64::MAX), 1_000);
+    }
+
+    #[test]
+    fn curve_rounds_up_for_the_maker() {
+        let curve = auction(1_010, 3, &[]);
+        // 1000 + 10 * 2/3 = 1006.67
+        assert_eq!(curve.amount_at(1_000, START + 1), 1_007);
+        // 1000 + 10 * 1/3 = 1003.33
+        assert_eq!(curve.amount_at(1_000, START + 2), 1_004);
+
+        let tiny = auction(1, 1_000, &[]);
+        assert_eq!(tiny.amount_at(0, START + 999), 1);
+        assert_eq!(tiny.amount_at(0, START + 1_000), 0);
+    }
+
+    #[test]
+    fn flat_curve() {
+        let flat = auction(500, 60, &[]);
+        for elapsed in [0, 1, 30, 59, 60, 61] {
+            assert_eq!(flat.amount_at(500, START + elapsed), 500);
+        }
+    }
+
+    #[test]
+    fn piecewise_curve_follows_each_segment() {
+        // 3000 → 2000 en 10 s, plano hasta los 20 s y 2000 → 1000 hasta los 100 s
+        let curve = auction(3_000, 100, &[(10, 2_000), (20, 2_000)]);
+        assert_eq!(curve.amount_at(1_000, START), 3_000);
+        assert_eq!(curve.amount_at(1_000, START + 5), 2_500);
+        assert_eq!(curve.amount_at(1_000, START + 10), 2_000);
+        assert_eq!(curve.amount_at(1_000, START + 15), 2_000);
+        assert_eq!(curve.amount_at(1_000, START + 20), 2_000);
+        assert_eq!(curve.amount_at(1_000, START + 60), 1_500);
+        assert_eq!(curve.amount_at(1_000, START + 100), 1_000);
+    }
+
+    #[test]
+    fn piecewise_curve_is_continuous_and_never_rises() {
+        let curve = auction(10_000, 300, &[(1, 9_000), (50, 8_999), (51, 5_000), (299, 4_000)]);
+        assert!(curve.validate(1_000).is_ok());
+
+        let mut previous = curve.amount_at(1_000, START);
+        for elapsed in 1..=301 {
+            let amount = curve.amount_at(1_000, START + elapsed);
+            assert!(amount <= previous, "sube en {elapsed}");
+            previous = amount;
+        }
+        for (delay, amount) in [(1, 9_000), (50, 8_999), (51, 5_000), (299, 4_000), (300, 1_000)] {
+            assert_eq!(curve.amount_at(1_000, START + delay), amount);
+        }
+    }
+
+    #[test]
+    fn curve_does_not_overflow() {
+        let curve = auction(u128::MAX, u32::MAX, &[(u32::MAX - 1, 1)]);
+        assert!(curve.validate(0).is_ok());
+        assert_eq!(curve.amount_at(0, START), u128::MAX);
+        assert_eq!(curve.amount_at(0, START + 1), u128::MAX - (u128::MAX - 1) / u128::from(u32::MAX - 1));
+        assert_eq!(curve.amount_at(0, START + u64::from(u32::MAX - 1)), 1);
+        assert_eq!(curve.amount_at(0, START + u64::from(u32::MAX)), 0);
+
+        let late = AuctionDetails { start_time: u64::MAX - 10, ..auction(2, 100, &[]) };
+        assert_eq!(late.amount_at(1, u64::MAX), 2);
+    }
+
+    #[test]
+    fn mul_div_ceil_is_exact() {
+        assert_eq!(mul_div_ceil(10, 1, 3), 4);
+        assert_eq!(mul_div_ceil(9, 1, 3), 3);
+        assert_eq!(mul_div_ceil(0, 5, 7), 0);
+        assert_eq!(mul_div_ceil(u128::MAX, 7, 7), u128::MAX);
+        assert_eq!(mul_div_ceil(u128::MAX, 0, 7), 0);
+    }
+
+    #[test]
+    fn validate_accepts_well_formed_curves() {
+        assert!(auction(2_000, 100, &[]).validate(1_000).is_ok());
+        assert!(auction(1_000, 100, &[]).validate(1_000).is_ok());
+        assert!(auction(2_000, 100, &[(1, 2_000), (99, 1_000)]).validate(1_000).is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_bad_curves() {
+        assert_eq!(auction(2_000, 0, &[]).validate(1_000), Err("La subasta debe durar al menos un segundo"));
+        assert_eq!(auction(999, 100, &[]).validate(1_000), Err("El importe de la subasta no puede subir"));
+        assert_eq!(
+            auction(2_000, 100, &[(10, 1_500), (20, 1_600)]).validate(1_000),
+            Err("El importe de la subasta no puede subir")
+        );
+        assert_eq!(
+            auction(2_000, 100, &[(10, 1_500), (10, 1_400)]).validate(1_000),
+            Err("Los puntos de la subasta deben estar ordenados dentro de su duración")
+        );
+        assert_eq!(
+            auction(2_000, 100, &[(0, 1_500)]).validate(1_000),
+            Err("Los puntos de la subasta deben estar ordenados dentro de su duración")
+        );
+        for delay in [100, 150] {
+            assert_eq!(
+                auction(2_000, 100, &[(delay, 1_500)]).validate(1_000),
+                Err("Los puntos de la subasta deben estar ordenados dentro de su duración")
+            );
+        }
+
+        let too_many: Vec<_> = (1..=MAX_AUCTION_POINTS as u32 + 1).map(|delay| (delay, 1_500)).collect();
+        assert_eq!(auction(2_000, 100, &too_many).validate(1_000), Err("La subasta tiene demasiados puntos"));
+    }
+}
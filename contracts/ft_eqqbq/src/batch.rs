@@ -0,0 +1,84 @@
+use near_sdk::json_types::U128;
+use near_sdk::{near, AccountId};
+
+use crate::asset::Asset;
+use crate::DepositInfo;
+
+/// Máximo de elementos en `claim_many`, `refund_many` y `cleanup`. En el peor
+/// caso cada elemento va a un receptor distinto y su grupo necesita 10 TGas
+/// para `ft_transfer`, 10 TGas para el callback y unos 20 TGas de tarifas de
+/// los receipts: 240 TGas para 6 elementos, que dejan margen para la propia
+/// llamada dentro de los 300 TGas de una transacción. `internal_batch_payout`
+/// comprueba además el gas adjunto.
+pub const MAX_BATCH_SIZE: usize = 6;
+
+/// Resultado de un elemento de `claim_many` o `refund_many`. Que salga bien
+/// significa que el pago se ha enviado; su resultado llega en el callback.
+#[near(serializers = [json])]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchResult {
+    pub escrow_id: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Escrows que ha tratado `cleanup`.
+#[near(serializers = [json])]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CleanupResult {
+    /// Escrows caducados cuya devolución al sender se ha enviado
+    pub refunded: Vec<String>,
+    /// Entradas reclamadas abandonadas que se han dado por pagadas y borrado
+    pub removed: Vec<String>,
+    /// Posición por la que seguir en la siguiente llamada; `None` al terminar
+    pub next_index: Option<U128>,
+}
+
+/// Pago pendiente de un depósito ya marcado como reclamado o devuelto.
+pub struct Payout {
+    pub escrow_id: String,
+    /// El depósito antes de marcarlo, para restaurarlo si el pago falla
+    pub deposit: DepositInfo,
+    pub receiver_id: AccountId,
+    pub amount: U128,
+}
+
+/// Pagos del mismo activo al mismo receptor, que se envían en una sola transferencia.
+pub struct PayoutGroup {
+    pub asset: Asset,
+    pub receiver_id: AccountId,
+    pub amount: U128,
+    pub payouts: Vec<(String, DepositInfo)>,
+}
+
+impl BatchResult {
+    pub fn new(escrow_id: String, result: Result<(), &str>) -> Self {
+        BatchResult { escrow_id, success: result.is_ok(), error: result.err().map(str::to_string) }
+    }
+}
+
+/// Agrupa los pagos por activo y receptor, en el orden en que aparece cada grupo.
+pub fn group_payouts(payouts: Vec<Payout>) -> Vec<PayoutGroup> {
+    let mut groups: Vec<PayoutGroup> = Vec::new();
+    for payout in payouts {
+        let group = match groups
+            .iter_mut()
+            .position(|group| group.asset == payout.deposit.asset && group.receiver_id == payout.receiver_id)
+        {
+            Some(i) => &mut groups[i],
+            None => {
+                groups.push(PayoutGroup {
+                    asset: payout.deposit.asset.clone(),
+                    receiver_id: payout.receiver_id.clone(),
+                    amount: U128(0),
+                    payouts: Vec::new(),
+                });
+                groups.last_mut().unwrap()
+            }
+        };
+        group.amount.0 += payout.amount.0;
+        group.payouts.push((payout.escrow_id, payout.deposit));
+    }
+    groups
+}
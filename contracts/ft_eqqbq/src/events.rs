@@ -0,0 +1,345 @@
+//! Eventos del escrow en formato NEP-297 con `standard: "fusion_escrow"`, para que
+//! el relayer pueda seguir los depósitos sin consultar `get_deposit_info`. Las
+//! acciones del owner también quedan registradas.
+//!
+//! Se emiten llamando a `.emit()`, igual que los eventos de
+//! `near_contract_standards::fungible_token::events`:
+//!
+//! `EVENT_JSON:{"standard":"fusion_escrow","version":"1.0.0","event":"escrow_claim","data":[...]}`
+//!
+//! `escrow_claim` y `escrow_refund` se emiten al enviar el pago, antes de saber
+//! si la transferencia sale bien, para que el secreto se conozca cuanto antes.
+//! Si falla, el callback emite `escrow_restore` y el depósito vuelve a estar
+//! abierto: el evento anterior queda anulado.
+//!
+//! `VERSION` sólo cambia cuando se publica un cambio del esquema, no con cada
+//! commit: la menor al añadir eventos o campos opcionales y la mayor al quitar
+//! o cambiar campos. Cada versión se apunta aquí con lo que cambió.
+//!
+//! ## Versiones
+//!
+//! - `1.0.0`: primera versión publicada. Ciclo de vida del escrow
+//!   (`escrow_create`, `escrow_claim`, `escrow_refund`, `escrow_restore`,
+//!   `escrow_expire`, `escrow_cleanup`), acciones del owner (`ownership_proposed`,
+//!   `ownership_transferred`, `pause_changed`, `token_set`, `token_removed`,
+//!   `rescue_delay_set`, `rescue_proposed`, `funds_rescued`, `protocol_fee_set`,
+//!   `fees_withdrawn`)
+//!   y órdenes firmadas (`order_filled`, `order_cancelled`, `nonces_invalidated`,
+//!   `epoch_increased`), con los campos de sus structs.
+
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::{env, serde_json, AccountIdRef};
+
+use crate::asset::Asset;
+use crate::evm::{ChainId, EvmAddress};
+
+const STANDARD: &str = "fusion_escrow";
+const VERSION: &str = "1.0.0";
+
+/// Nuevo depósito en `ft_on_transfer` o `recive_near`.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EscrowCreate<'a> {
+    pub escrow_id: &'a str,
+    pub hashlock: &'a str,
+    pub sender: &'a AccountIdRef,
+    pub taker: &'a AccountIdRef,
+    pub recipient: &'a AccountIdRef,
+    pub asset: &'a Asset,
+    pub amount: U128,
+    pub safety_deposit: U128,
+    pub order_hash: &'a str,
+    pub src_chain_id: ChainId,
+    pub dst_chain_id: ChainId,
+    pub evm_maker: EvmAddress,
+    pub dst_token: EvmAddress,
+    pub dst_amount: U128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parts: Option<u16>,
+}
+
+/// Retirada con el secreto revelado. En rellenos parciales `index` es el índice
+/// del secreto y `amount` lo liberado en este relleno. `amount` incluye las
+/// comisiones; el recipient recibe `amount - protocol_fee - integrator_fee`.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EscrowClaim<'a> {
+    pub escrow_id: &'a str,
+    pub secret: &'a str,
+    pub recipient: &'a AccountIdRef,
+    pub executor: &'a AccountIdRef,
+    pub amount: U128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<u64>,
+    pub protocol_fee: U128,
+    pub integrator_fee: U128,
+}
+
+/// Devolución de los fondos al sender.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EscrowRefund<'a> {
+    pub escrow_id: &'a str,
+    pub sender: &'a AccountIdRef,
+    pub executor: &'a AccountIdRef,
+    pub amount: U128,
+}
+
+/// El pago de un `escrow_claim` o `escrow_refund` anterior (`reverted`) falló y
+/// `amount` vuelve al escrow, que se puede volver a reclamar o devolver. Si el
+/// escrow ya se había cerrado, `amount` vuelve al sender.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EscrowRestore<'a> {
+    pub escrow_id: &'a str,
+    pub reverted: &'static str,
+    pub amount: U128,
+}
+
+/// El depósito llegó a la cancelación sin reclamarse. `expired_at` es el inicio
+/// de la cancelación en nanosegundos.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EscrowExpire<'a> {
+    pub escrow_id: &'a str,
+    pub sender: &'a AccountIdRef,
+    pub expired_at: U64,
+}
+
+/// El owner propone a `new_owner` como sucesor; tiene que aceptarlo.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OwnershipProposed<'a> {
+    pub owner: &'a AccountIdRef,
+    pub new_owner: &'a AccountIdRef,
+}
+
+/// `new_owner` aceptó la propiedad del contrato.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OwnershipTransferred<'a> {
+    pub old_owner: &'a AccountIdRef,
+    pub new_owner: &'a AccountIdRef,
+}
+
+/// Se pausaron (`paused: true`) o reanudaron los depósitos nuevos.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PauseChanged<'a> {
+    pub owner: &'a AccountIdRef,
+    pub paused: bool,
+}
+
+/// Token añadido a la allowlist o con límites nuevos.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenSet<'a> {
+    pub owner: &'a AccountIdRef,
+    pub token_id: &'a AccountIdRef,
+    pub min_amount: U128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_amount: Option<U128>,
+}
+
+/// Token quitado de la allowlist.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenRemoved<'a> {
+    pub owner: &'a AccountIdRef,
+    pub token_id: &'a AccountIdRef,
+}
+
+/// Nuevo retraso de rescate en segundos.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RescueDelaySet<'a> {
+    pub owner: &'a AccountIdRef,
+    pub rescue_delay: u32,
+}
+
+/// El owner propone rescatar `amount` de `asset`; se ejecuta pasado el retraso de rescate.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RescueProposed<'a> {
+    pub owner: &'a AccountIdRef,
+    pub asset: &'a Asset,
+    pub amount: U128,
+}
+
+/// Excedente no vinculado a ningún escrow enviado a `executor`.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FundsRescued<'a> {
+    pub executor: &'a AccountIdRef,
+    pub asset: &'a Asset,
+    pub amount: U128,
+}
+
+/// Nueva comisión del protocolo en puntos básicos.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProtocolFeeSet<'a> {
+    pub owner: &'a AccountIdRef,
+    pub protocol_fee_bps: u16,
+}
+
+/// Retirada de comisiones del protocolo o de un integrador.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeesWithdrawn<'a> {
+    pub receiver: &'a AccountIdRef,
+    pub asset: &'a Asset,
+    pub amount: U128,
+}
+
+/// Entrada borrada por `cleanup`. `executor` se lleva `bounty` del almacenamiento
+/// liberado; el resto vuelve al sender.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EscrowCleanup<'a> {
+    pub escrow_id: &'a str,
+    pub executor: &'a AccountIdRef,
+    pub bounty: U128,
+}
+
+/// Orden firmada rellenada por `taker` con `fill_order`.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OrderFilled<'a> {
+    pub order_hash: &'a str,
+    pub maker: &'a AccountIdRef,
+    pub taker: &'a AccountIdRef,
+    pub nonce: u64,
+    pub escrow_id: &'a str,
+}
+
+/// Orden firmada cancelada por su maker con `cancel_order`.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OrderCancelled<'a> {
+    pub maker: &'a AccountIdRef,
+    pub order_hash: &'a str,
+}
+
+/// Nonces de la serie `series` marcados como usados con `invalidate_nonces`.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NoncesInvalidated<'a> {
+    pub maker: &'a AccountIdRef,
+    pub series: u64,
+    /// Bitmap acumulado de la serie
+    pub bitmap: U128,
+}
+
+/// Nueva época del maker; las órdenes firmadas en épocas anteriores ya no valen.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EpochIncreased<'a> {
+    pub maker: &'a AccountIdRef,
+    pub epoch: u64,
+}
+
+macro_rules! impl_emit {
+    ($($event:ident),*) => {
+        $(
+            impl $event<'_> {
+                pub fn emit(self) {
+                    Self::emit_many(&[self])
+                }
+
+                pub fn emit_many(data: &[$event<'_>]) {
+                    EscrowEvent { standard: STANDARD, version: VERSION, event_kind: EscrowEventKind::$event(data) }.emit()
+                }
+            }
+        )*
+    };
+}
+
+impl_emit!(
+    EscrowCreate,
+    EscrowClaim,
+    EscrowRefund,
+    EscrowRestore,
+    EscrowExpire,
+    OwnershipProposed,
+    OwnershipTransferred,
+    PauseChanged,
+    TokenSet,
+    TokenRemoved,
+    RescueDelaySet,
+    RescueProposed,
+    FundsRescued,
+    ProtocolFeeSet,
+    FeesWithdrawn,
+    EscrowCleanup,
+    OrderFilled,
+    OrderCancelled,
+    NoncesInvalidated,
+    EpochIncreased
+);
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+struct EscrowEvent<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event_kind: EscrowEventKind<'a>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data")]
+#[serde(rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)]
+enum EscrowEventKind<'a> {
+    EscrowCreate(&'a [EscrowCreate<'a>]),
+    EscrowClaim(&'a [EscrowClaim<'a>]),
+    EscrowRefund(&'a [EscrowRefund<'a>]),
+    EscrowRestore(&'a [EscrowRestore<'a>]),
+    EscrowExpire(&'a [EscrowExpire<'a>]),
+    OwnershipProposed(&'a [OwnershipProposed<'a>]),
+    OwnershipTransferred(&'a [OwnershipTransferred<'a>]),
+    PauseChanged(&'a [PauseChanged<'a>]),
+    TokenSet(&'a [TokenSet<'a>]),
+    TokenRemoved(&'a [TokenRemoved<'a>]),
+    RescueDelaySet(&'a [RescueDelaySet<'a>]),
+    RescueProposed(&'a [RescueProposed<'a>]),
+    FundsRescued(&'a [FundsRescued<'a>]),
+    ProtocolFeeSet(&'a [ProtocolFeeSet<'a>]),
+    FeesWithdrawn(&'a [FeesWithdrawn<'a>]),
+    EscrowCleanup(&'a [EscrowCleanup<'a>]),
+    OrderFilled(&'a [OrderFilled<'a>]),
+    OrderCancelled(&'a [OrderCancelled<'a>]),
+    NoncesInvalidated(&'a [NoncesInvalidated<'a>]),
+    EpochIncreased(&'a [EpochIncreased<'a>]),
+}
+
+impl EscrowEvent<'_> {
+    fn emit(self) {
+        // Los eventos no pueden fallar al serializarse
+        let json = serde_json::to_string(&self).unwrap_or_else(|_| env::abort());
+        env::log_str(&format!("EVENT_JSON:{}", json));
+    }
+}
@@ -0,0 +1,97 @@
+use near_sdk::near;
+
+/// Dirección de EVM (20 bytes). En JSON va en hexadecimal con `0x`, en
+/// mayúsculas o minúsculas, y se devuelve siempre en minúsculas.
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct EvmAddress(pub [u8; 20]);
+
+/// ID de cadena de EIP-155. Nunca es 0.
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(try_from = "u64", into = "u64")]
+pub struct ChainId(u64);
+
+impl EvmAddress {
+    /// `address(0)`: el token nativo en los escrows de EVM
+    pub const ZERO: EvmAddress = EvmAddress([0; 20]);
+}
+
+impl ChainId {
+    /// NEAR mainnet
+    pub const NEAR: ChainId = ChainId(397);
+
+    pub fn new(id: u64) -> Result<Self, &'static str> {
+        if id == 0 {
+            return Err("El ID de cadena no puede ser 0");
+        }
+        Ok(ChainId(id))
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0
+    }
+}
+
+impl TryFrom<String> for EvmAddress {
+    type Error = &'static str;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        const ERROR: &str = "La dirección de EVM debe ser de 20 bytes en hexadecimal con 0x";
+        let digits = value.strip_prefix("0x").ok_or(ERROR)?;
+        let bytes = hex::decode(digits).map_err(|_| ERROR)?;
+        Ok(EvmAddress(bytes.try_into().map_err(|_| ERROR)?))
+    }
+}
+
+impl From<EvmAddress> for String {
+    fn from(address: EvmAddress) -> Self {
+        format!("0x{}", hex::encode(address.0))
+    }
+}
+
+impl TryFrom<u64> for ChainId {
+    type Error = &'static str;
+
+    fn try_from(id: u64) -> Result<Self, Self::Error> {
+        ChainId::new(id)
+    }
+}
+
+impl From<ChainId> for u64 {
+    fn from(id: ChainId) -> Self {
+        id.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::serde_json;
+
+    #[test]
+    fn evm_address_roundtrip_is_lowercase() {
+        let address: EvmAddress = serde_json::from_str(r#""0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48""#).unwrap();
+        assert_eq!(serde_json::to_string(&address).unwrap(), r#""0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48""#);
+    }
+
+    #[test]
+    fn evm_address_rejects_bad_input() {
+        for value in [
+            r#""a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48""#,
+            r#""0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb""#,
+            r#""0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb4800""#,
+            r#""0xz0b86991c6218b36c1d19d4a2e9eb0ce3606eb48""#,
+        ] {
+            assert!(serde_json::from_str::<EvmAddress>(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn chain_id_rejects_zero() {
+        assert!(serde_json::from_str::<ChainId>("0").is_err());
+        assert_eq!(serde_json::from_str::<ChainId>("42161").unwrap().get(), 42161);
+        assert_eq!(serde_json::to_string(&ChainId::NEAR).unwrap(), "397");
+    }
+}
@@ -0,0 +1,144 @@
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::U128;
+use near_sdk::{near, AccountId};
+
+use crate::asset::Asset;
+
+/// Comisión máxima, tanto la del protocolo como la de un integrador (10%)
+pub const MAX_FEE_BPS: u16 = 1_000;
+
+const BPS_DENOMINATOR: u128 = 10_000;
+
+/// Comisión de un integrador para una orden concreta.
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IntegratorFee {
+    pub recipient: AccountId,
+    pub fee_bps: u16,
+}
+
+/// Comisiones acumuladas de un activo.
+#[near(serializers = [json])]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeeBalance {
+    pub asset: Asset,
+    pub amount: U128,
+}
+
+impl IntegratorFee {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.fee_bps > MAX_FEE_BPS {
+            return Err("La comisión del integrador supera el máximo");
+        }
+        Ok(())
+    }
+}
+
+/// `amount * fee_bps / 10_000` redondeando hacia abajo, sin desbordar para
+/// ningún `amount`.
+pub fn fee_amount(amount: u128, fee_bps: u16) -> u128 {
+    let fee_bps = u128::from(fee_bps);
+    amount / BPS_DENOMINATOR * fee_bps + amount % BPS_DENOMINATOR * fee_bps / BPS_DENOMINATOR
+}
+
+/// Suma `amount` al saldo de `asset` en un libro de comisiones.
+pub fn credit(ledger: &mut UnorderedMap<Asset, u128>, asset: &Asset, amount: u128) {
+    if amount == 0 {
+        return;
+    }
+    let balance = ledger.get(asset).unwrap_or(0);
+    ledger.insert(asset, &(balance + amount));
+}
+
+/// Crea la entrada de `asset` a cero si no existe. Devuelve si la ha creado.
+pub fn register(ledger: &mut UnorderedMap<Asset, u128>, asset: &Asset) -> bool {
+    if ledger.get(asset).is_some() {
+        return false;
+    }
+    ledger.insert(asset, &0);
+    true
+}
+
+/// Resta `amount` del saldo de `asset`; borra la entrada si queda a cero.
+pub fn debit(ledger: &mut UnorderedMap<Asset, u128>, asset: &Asset, amount: u128) -> Result<(), &'static str> {
+    let balance = ledger.get(asset).unwrap_or(0);
+    let remaining = balance.checked_sub(amount).ok_or("No hay suficientes comisiones acumuladas")?;
+    if remaining == 0 {
+        ledger.remove(asset);
+    } else {
+        ledger.insert(asset, &remaining);
+    }
+    Ok(())
+}
+
+/// Resta `amount` del saldo de una entrada registrada y la deja aunque quede a
+/// cero, porque su almacenamiento ya está pagado. Devuelve el saldo restante.
+pub fn debit_registered(ledger: &mut UnorderedMap<Asset, u128>, asset: &Asset, amount: u128) -> Result<u128, &'static str> {
+    let balance = ledger.get(asset).ok_or("No hay suficientes comisiones acumuladas")?;
+    let remaining = balance.checked_sub(amount).ok_or("No hay suficientes comisiones acumuladas")?;
+    ledger.insert(asset, &remaining);
+    Ok(remaining)
+}
+
+/// Saldos de un libro de comisiones.
+pub fn balances(ledger: &UnorderedMap<Asset, u128>) -> Vec<FeeBalance> {
+    ledger.iter().map(|(asset, amount)| FeeBalance { asset, amount: U128(amount) }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fee_amount_rounds_down() {
+        assert_eq!(fee_amount(10_000, 30), 30);
+        assert_eq!(fee_amount(9_999, 30), 29);
+        assert_eq!(fee_amount(333, 1), 0);
+        assert_eq!(fee_amount(1_000_000, 0), 0);
+    }
+
+    #[test]
+    fn fee_amount_does_not_overflow() {
+        assert_eq!(fee_amount(u128::MAX, 10_000), u128::MAX);
+        assert_eq!(fee_amount(u128::MAX, MAX_FEE_BPS), u128::MAX / 10);
+    }
+
+    #[test]
+    fn ledger_credit_and_debit() {
+        let mut ledger = UnorderedMap::new(b"f");
+        let near = Asset::Near;
+
+        credit(&mut ledger, &near, 0);
+        assert!(ledger.is_empty());
+
+        credit(&mut ledger, &near, 10);
+        credit(&mut ledger, &near, 5);
+        assert_eq!(balances(&ledger), vec![FeeBalance { asset: near.clone(), amount: U128(15) }]);
+
+        assert!(debit(&mut ledger, &near, 16).is_err());
+        assert!(debit(&mut ledger, &near, 15).is_ok());
+        assert!(ledger.is_empty());
+    }
+
+    #[test]
+    fn registered_entries_stay_at_zero() {
+        let mut ledger = UnorderedMap::new(b"f");
+        let near = Asset::Near;
+
+        assert!(register(&mut ledger, &near));
+        assert!(!register(&mut ledger, &near));
+        assert_eq!(balances(&ledger), vec![FeeBalance { asset: near.clone(), amount: U128(0) }]);
+
+        credit(&mut ledger, &near, 10);
+        assert!(!register(&mut ledger, &near));
+        assert_eq!(debit_registered(&mut ledger, &near, 10), Ok(0));
+        assert_eq!(ledger.get(&near), Some(0));
+    }
+
+    #[test]
+    fn integrator_fee_is_capped() {
+        let fee = |fee_bps| IntegratorFee { recipient: "integrator.near".parse().unwrap(), fee_bps };
+        assert!(fee(MAX_FEE_BPS).validate().is_ok());
+        assert!(fee(MAX_FEE_BPS + 1).validate().is_err());
+    }
+}
@@ -0,0 +1,31 @@
+use near_sdk::{env, near};
+
+/// Algoritmo con el que se calcula el hashlock de un depósito.
+/// `Keccak256` es el que usan los escrows de Fusion+ en EVM.
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    Sha256,
+    Keccak256,
+}
+
+impl HashAlgorithm {
+    pub fn hash(&self, preimage: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgorithm::Sha256 => env::sha256(preimage),
+            HashAlgorithm::Keccak256 => env::keccak256(preimage),
+        }
+    }
+}
+
+/// Valida un valor de 32 bytes en hexadecimal (hashlock, order hash...) y lo devuelve
+/// normalizado en minúsculas.
+pub fn normalize_bytes32(value: &str) -> Option<String> {
+    hex::decode(value).ok().filter(|bytes| bytes.len() == 32).map(hex::encode)
+}
+
+/// Decodifica el secreto (preimagen) enviado en hexadecimal.
+pub fn decode_secret(secret: &str) -> Result<Vec<u8>, &'static str> {
+    hex::decode(secret).map_err(|_| "El secreto debe estar en hexadecimal")
+}
@@ -0,0 +1,138 @@
+use near_sdk::json_types::U128;
+use near_sdk::{env, near, AccountId};
+
+use crate::asset::Asset;
+use crate::hashlock::normalize_bytes32;
+use crate::timelocks::Timelocks;
+
+/// Datos fijos de un escrow, como los `Immutables` de Fusion+. Su hash es el
+/// `escrow_id` con el que se guarda el depósito.
+#[near(serializers = [json])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Immutables {
+    /// Hash de la orden en hexadecimal (32 bytes)
+    pub order_hash: String,
+    /// hash(secreto) o raíz Merkle en hexadecimal (32 bytes)
+    pub hashlock: String,
+    /// Quien deposita los fondos (el sender)
+    pub maker: AccountId,
+    pub taker: AccountId,
+    pub token: Asset,
+    pub amount: U128,
+    pub safety_deposit: U128,
+    pub timelocks: Timelocks,
+}
+
+impl Immutables {
+    /// `keccak256` de los campos codificados en palabras de 32 bytes, como hace
+    /// `abi.encode` en el `EscrowFactory` de EVM:
+    ///
+    /// - `order_hash` y `hashlock` tal cual
+    /// - cuentas como `keccak256(account_id)`; el token NEAR es la palabra cero,
+    ///   igual que `address(0)` para el nativo
+    /// - importes como `uint256` big-endian
+    /// - timelocks empaquetados en un `uint256`, la etapa `i` en los bits `32 * i`;
+    ///   `deployedAt` va a cero porque el ID se conoce antes de crear el depósito
+    pub fn escrow_id(&self) -> Result<String, &'static str> {
+        let order_hash = word_from_hex(&self.order_hash).ok_or("El order_hash debe ser de 32 bytes en hexadecimal")?;
+        let hashlock = word_from_hex(&self.hashlock).ok_or("El hashlock debe ser de 32 bytes en hexadecimal")?;
+        let token = match &self.token {
+            Asset::Near => [0; 32],
+            Asset::Ft { token_id } => account_word(token_id),
+        };
+
+        let words = [
+            order_hash,
+            hashlock,
+            account_word(&self.maker),
+            account_word(&self.taker),
+            token,
+            amount_word(self.amount.0),
+            amount_word(self.safety_deposit.0),
+            timelocks_word(&self.timelocks),
+        ];
+        Ok(hex::encode(env::keccak256(&words.concat())))
+    }
+}
+
+fn word_from_hex(value: &str) -> Option<[u8; 32]> {
+    hex::decode(normalize_bytes32(value)?).ok()?.try_into().ok()
+}
+
+fn account_word(account_id: &AccountId) -> [u8; 32] {
+    env::keccak256_array(account_id.as_bytes())
+}
+
+fn amount_word(amount: u128) -> [u8; 32] {
+    let mut word = [0; 32];
+    word[16..].copy_from_slice(&amount.to_be_bytes());
+    word
+}
+
+fn timelocks_word(timelocks: &Timelocks) -> [u8; 32] {
+    let stages = [
+        timelocks.withdrawal,
+        timelocks.public_withdrawal,
+        timelocks.cancellation,
+        timelocks.public_cancellation,
+    ];
+    let mut word = [0; 32];
+    for (i, stage) in stages.iter().enumerate() {
+        // La etapa 0 ocupa los 4 bytes menos significativos
+        let end = 32 - 4 * i;
+        word[end - 4..end].copy_from_slice(&stage.to_be_bytes());
+    }
+    word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn immutables() -> Immutables {
+        Immutables {
+            order_hash: "ab".repeat(32),
+            hashlock: "cd".repeat(32),
+            maker: "alice.near".parse().unwrap(),
+            taker: "bob.near".parse().unwrap(),
+            token: Asset::Near,
+            amount: U128(100),
+            safety_deposit: U128(0),
+            timelocks: Timelocks::DEFAULT,
+        }
+    }
+
+    #[test]
+    fn escrow_id_is_deterministic_and_case_insensitive() {
+        let upper = Immutables { hashlock: "CD".repeat(32), ..immutables() };
+        assert_eq!(immutables().escrow_id(), upper.escrow_id());
+    }
+
+    #[test]
+    fn escrow_id_changes_with_every_field() {
+        let id = immutables().escrow_id().unwrap();
+        let variants = [
+            Immutables { order_hash: "ac".repeat(32), ..immutables() },
+            Immutables { maker: "carol.near".parse().unwrap(), ..immutables() },
+            Immutables { token: Asset::Ft { token_id: "token.near".parse().unwrap() }, ..immutables() },
+            Immutables { amount: U128(101), ..immutables() },
+            Immutables { safety_deposit: U128(1), ..immutables() },
+            Immutables { timelocks: Timelocks { public_cancellation: 100_000, ..Timelocks::DEFAULT }, ..immutables() },
+        ];
+        for variant in variants {
+            assert_ne!(variant.escrow_id().unwrap(), id);
+        }
+    }
+
+    #[test]
+    fn timelocks_packed_like_fusion() {
+        let word = timelocks_word(&Timelocks { withdrawal: 1, public_withdrawal: 2, cancellation: 3, public_cancellation: 4 });
+        assert_eq!(&word[16..], &[0, 0, 0, 4, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 1]);
+        assert_eq!(&word[..16], &[0; 16]);
+    }
+
+    #[test]
+    fn escrow_id_rejects_bad_hex() {
+        assert!(Immutables { hashlock: "zz".to_string(), ..immutables() }.escrow_id().is_err());
+    }
+}
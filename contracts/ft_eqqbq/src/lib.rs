@@ -1,16 +1,165 @@
-use near_sdk::{env, near, AccountId, PromiseOrValue, Promise, PanicOnDefault, NearToken, require};
-use near_sdk::collections::UnorderedMap;
-use near_sdk::json_types::{U128, U64};
+use near_sdk::{env, near, AccountId, PromiseOrValue, Promise, PanicOnDefault, NearToken, require, Gas, PromiseError};
+use near_sdk::{CurveType, PublicKey};
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_sdk::{assert_one_yocto, BorshStorageKey};
+use near_sdk::borsh::BorshSerialize;
+use near_sdk::collections::{LookupMap, LookupSet, UnorderedMap, UnorderedSet};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
 
-pub const STORAGE_COST: NearToken = NearToken::from_millinear(1);
-const TIMELOCK_SECONDS: u64 = 60 * 60 * 24; // 24 horas
+mod asset;
+mod auction;
+mod batch;
+pub mod events;
+mod evm;
+mod fees;
+mod hashlock;
+mod immutables;
+mod merkle;
+mod migration;
+mod msg;
+mod order;
+mod rescue;
+mod stats;
+mod timelocks;
+mod tokens;
+mod views;
+
+pub use crate::asset::Asset;
+pub use crate::batch::{BatchResult, CleanupResult};
+pub use crate::evm::{ChainId, EvmAddress};
+use crate::batch::{group_payouts, Payout, PayoutGroup, MAX_BATCH_SIZE};
+pub use crate::fees::{FeeBalance, IntegratorFee};
+pub use crate::hashlock::HashAlgorithm;
+pub use crate::immutables::Immutables;
+pub use crate::msg::{EscrowMsg, EscrowMsgV1};
+pub use crate::auction::{AuctionDetails, AuctionPoint};
+pub use crate::order::Order;
+pub use crate::rescue::RescueProposal;
+use crate::order::nonce_slot;
+use crate::migration::{upgrade_deposits, write_state_version, VersionedContract, VersionedDepositInfo};
+use crate::msg::EscrowParams;
+pub use crate::stats::{LockedValue, Stats, StatsView};
+pub use crate::timelocks::{Stage, Timelocks};
+pub use crate::tokens::{SupportedToken, TokenLimits};
+pub use crate::views::{ConfigView, DepositView, DepositsPage, EscrowStatus};
+use crate::views::paginate_set;
+use crate::events::{
+    EscrowCleanup, EscrowClaim, EscrowCreate, EscrowExpire, EscrowRefund, EscrowRestore, FeesWithdrawn, FundsRescued, OwnershipProposed,
+    EpochIncreased, NoncesInvalidated, OrderCancelled, OrderFilled, OwnershipTransferred, PauseChanged, ProtocolFeeSet, RescueDelaySet,
+    RescueProposed, TokenRemoved, TokenSet,
+};
+use crate::fees::{fee_amount, MAX_FEE_BPS};
+use crate::timelocks::NANOS_PER_SECOND;
+use crate::hashlock::{decode_secret, normalize_bytes32};
+
+const GAS_FOR_RESOLVE_PAYOUT: Gas = Gas::from_tgas(10);
+const GAS_FOR_RESOLVE_FILL: Gas = Gas::from_tgas(10);
+const GAS_FOR_FT_BALANCE_OF: Gas = Gas::from_tgas(5);
+const GAS_FOR_RESOLVE_RESCUE: Gas = Gas::from_tgas(30);
+const GAS_FOR_RESOLVE_RESCUE_TRANSFER: Gas = Gas::from_tgas(5);
+const GAS_FOR_RESOLVE_FEE_WITHDRAWAL: Gas = Gas::from_tgas(10);
+const GAS_FOR_RESOLVE_FT_WITHDRAWAL: Gas = Gas::from_tgas(10);
+/// Gas que se deja para terminar un lote después de programar sus pagos
+const GAS_RESERVED_FOR_BATCH: Gas = Gas::from_tgas(5);
+/// Lo que se quema al crear la transferencia de un grupo y su callback (tarifas
+/// de los receipts y de sus argumentos); medido en unos 19 TGas
+const GAS_FOR_BATCH_GROUP_RECEIPTS: Gas = Gas::from_tgas(20);
+
+/// Por defecto un rescate se ejecuta un mes después de proponerlo
+pub const DEFAULT_RESCUE_DELAY: u32 = 60 * 60 * 24 * 30;
+
+/// Parte del almacenamiento liberado que `cleanup` paga a quien lo llama (10%)
+pub const CLEANUP_BOUNTY_BPS: u16 = 1_000;
+
+/// Segundos desde el inicio de la cancelación tras los que una entrada reclamada
+/// que sigue en `deposits` se considera abandonada. Los pagos en curso se
+/// resuelven en unos pocos bloques.
+const SETTLED_GRACE_PERIOD: u64 = 60 * 60;
+
+#[derive(BorshSerialize, BorshStorageKey)]
+#[borsh(crate = "near_sdk::borsh")]
+enum StorageKey {
+    /// Primera variante: mismo prefijo (0) que usaba `UnorderedMap::new(0)`
+    Deposits,
+    NearBalances,
+    DepositsBySender,
+    DepositsBySenderInner { account_hash: Vec<u8> },
+    DepositsByTaker,
+    DepositsByTakerInner { account_hash: Vec<u8> },
+    DepositsByToken,
+    DepositsByTokenInner { account_hash: Vec<u8> },
+    SupportedTokens,
+    LockedValue,
+    RevealedHashlocks,
+    ProtocolFees,
+    IntegratorFees,
+    IntegratorFeesInner { account_hash: Vec<u8> },
+    FeesOwed,
+    OrderKeys,
+    Nonces,
+    FtBalances,
+    FtBalancesTotal,
+    CancelledOrders,
+    Epochs,
+    RescueProposals,
+}
 
 #[near(contract_state)]
 #[derive(PanicOnDefault)]
 pub struct Contract {
-    /// Almacena los depósitos vinculados a un hash(secreto)
-    pub deposits: UnorderedMap<String, DepositInfo>,
-    pub deposit_number: U128,
+    /// Almacena los depósitos por su escrow_id: el hash de sus `Immutables`
+    pub deposits: UnorderedMap<String, VersionedDepositInfo>,
+    pub owner_id: AccountId,
+    /// Sucesor propuesto por el owner; pasa a ser owner cuando acepta
+    pub pending_owner_id: Option<AccountId>,
+    /// Si está pausado no se crean depósitos nuevos; reclamar y devolver sigue funcionando
+    pub paused: bool,
+    /// Timelocks de los depósitos cuyo msg no los indica
+    pub default_timelocks: Timelocks,
+    /// Allowlist de tokens NEP-141 que se aceptan en `ft_on_transfer`, con sus límites
+    pub supported_tokens: UnorderedMap<AccountId, TokenLimits>,
+    pub stats: Stats,
+    /// Importe bloqueado en escrows activos por activo
+    pub locked_value: UnorderedMap<Asset, u128>,
+    /// Hashes de los secretos ya revelados (con el algoritmo de su escrow) y
+    /// cuándo se revelaron. Se guardan para siempre: un escrow nuevo con uno de
+    /// ellos como hashlock lo podría vaciar cualquiera. Los paga el sender al
+    /// crear el escrow.
+    pub revealed_hashlocks: LookupMap<String, u64>,
+    /// NEAR que se debe fuera de los importes bloqueados: saldos prepagados y
+    /// depósitos de seguridad. El almacenamiento ya lo cubre el propio stake.
+    pub near_liabilities: u128,
+    /// Segundos desde que el owner propone un rescate hasta que lo puede ejecutar
+    pub rescue_delay: u32,
+    /// Rescate propuesto para cada activo; como mucho uno por activo
+    pub rescue_proposals: LookupMap<Asset, RescueProposal>,
+    /// Comisión del protocolo en puntos básicos para los escrows nuevos
+    pub protocol_fee_bps: u16,
+    /// Comisiones del protocolo por activo; las retira el owner
+    pub protocol_fees: UnorderedMap<Asset, u128>,
+    /// Comisiones de cada integrador por activo
+    pub integrator_fees: LookupMap<AccountId, UnorderedMap<Asset, u128>>,
+    /// Total de comisiones sin retirar por activo (protocolo más integradores)
+    pub fees_owed: UnorderedMap<Asset, u128>,
+    /// NEAR prepagado por cuenta para los depósitos de seguridad de escrows NEP-141
+    pub near_balances: LookupMap<AccountId, u128>,
+    /// Tokens NEP-141 depositados por cada cuenta, por (cuenta, token), con los
+    /// que se rellenan sus órdenes firmadas
+    pub ft_balances: LookupMap<(AccountId, AccountId), u128>,
+    /// Suma de `ft_balances` por token
+    pub ft_balances_total: LookupMap<AccountId, u128>,
+    /// Clave ed25519 con la que firma órdenes cada maker
+    pub order_keys: LookupMap<AccountId, [u8; 32]>,
+    /// Bitmap de nonces usados por (maker, serie); ver `order::nonce_slot`
+    pub nonces: LookupMap<(AccountId, u64), u128>,
+    /// Órdenes canceladas por su maker, por (maker, order_hash)
+    pub cancelled_orders: LookupSet<(AccountId, String)>,
+    /// Época actual de cada maker; sin entrada es 0
+    pub epochs: LookupMap<AccountId, u64>,
+    /// Índices secundarios: escrow_ids de los depósitos de cada sender, taker y token NEP-141
+    pub deposits_by_sender: LookupMap<AccountId, UnorderedSet<String>>,
+    pub deposits_by_taker: LookupMap<AccountId, UnorderedSet<String>>,
+    pub deposits_by_token: LookupMap<AccountId, UnorderedSet<String>>,
 }
 
 #[near(serializers = [json, borsh])]
@@ -21,19 +170,290 @@ pub struct DepositInfo {
     pub amount: U128,
     pub timestamp: u64,
     pub claimed: bool,
+    /// hash(secreto) en hexadecimal
+    pub hashlock: String,
+    pub hash_algorithm: HashAlgorithm,
+    pub asset: Asset,
+    /// Resolver que puede reclamar y cancelar en las ventanas exclusivas
+    pub taker: AccountId,
+    /// Etapas contadas desde `timestamp`
+    pub timelocks: Timelocks,
+    /// Beneficiario: recibe los fondos al reclamar, llame quien llame
+    pub recipient: AccountId,
+    /// Hash de la orden de Fusion+ en hexadecimal
+    pub order_hash: String,
+    pub src_chain_id: ChainId,
+    pub dst_chain_id: ChainId,
+    /// Maker de la orden en EVM, para casar el escrow con el de la otra cadena
+    pub evm_maker: EvmAddress,
+    /// Token y importe que el maker espera recibir en la cadena de destino
+    pub dst_token: EvmAddress,
+    pub dst_amount: U128,
+    pub safety_deposit: U128,
+    /// Quien adjuntó `safety_deposit` si no salió del sender: el resolver que
+    /// rellenó la orden firmada
+    pub safety_deposit_payer: Option<AccountId>,
+    /// Número de partes si `hashlock` es una raíz Merkle de secretos
+    pub parts: Option<u16>,
+    /// Importe ya liberado con rellenos parciales
+    pub filled: U128,
+    /// Se está devolviendo al sender (el pago aún no se ha resuelto)
+    pub refunded: bool,
+    /// yoctoNEAR cobrados al sender por el almacenamiento del depósito; se le
+    /// devuelven cuando se borra la entrada
+    pub storage_deposit: U128,
+    /// yoctoNEAR prepagados por el sender para los hashes revelados que aún no
+    /// se han guardado. Se gastan al guardarlos y lo que sobra se le devuelve
+    /// cuando se borra la entrada.
+    pub revealed_deposit: U128,
+    /// Comisión del protocolo vigente al crear el depósito
+    pub protocol_fee_bps: u16,
+    pub integrator_fee: Option<IntegratorFee>,
+}
+
+/// De dónde sale el NEAR para el almacenamiento y el depósito de seguridad.
+enum NearFunding {
+    /// Del NEAR adjunto a `recive_near`; el resto es el importe del depósito
+    Attached,
+    /// Del saldo prepagado del sender (`deposit_near`)
+    Balance,
+    /// Del saldo prepagado del sender, salvo el depósito de seguridad, que lo
+    /// adjunta este resolver en `fill_order`
+    Resolver(AccountId),
+}
+
+impl DepositInfo {
+    fn check_not_settled(&self) -> Result<(), &'static str> {
+        if self.claimed {
+            return Err("Ya fueron reclamados");
+        }
+        if self.refunded {
+            return Err("Ya fueron devueltos");
+        }
+        Ok(())
+    }
+
+    fn assert_not_settled(&self) {
+        self.check_not_settled().unwrap_or_else(|err| env::panic_str(err));
+    }
+
+    /// Comisiones del protocolo y del integrador sobre `amount` liberado.
+    fn fees(&self, amount: u128) -> (u128, u128) {
+        let integrator_fee = self.integrator_fee.as_ref().map_or(0, |fee| fee_amount(amount, fee.fee_bps));
+        (fee_amount(amount, self.protocol_fee_bps), integrator_fee)
+    }
+
+    pub fn status(&self) -> EscrowStatus {
+        if self.refunded {
+            return EscrowStatus::Refunded;
+        }
+        if self.claimed {
+            return EscrowStatus::Claimed;
+        }
+        match self.timelocks.current_stage(self.timestamp) {
+            Stage::Locked => EscrowStatus::Active,
+            Stage::Withdrawal | Stage::PublicWithdrawal => EscrowStatus::Claimable,
+            Stage::Cancellation | Stage::PublicCancellation => EscrowStatus::Refundable,
+        }
+    }
+
+    pub fn immutables(&self) -> Immutables {
+        Immutables {
+            order_hash: self.order_hash.clone(),
+            hashlock: self.hashlock.clone(),
+            maker: self.sender.clone(),
+            taker: self.taker.clone(),
+            token: self.asset.clone(),
+            amount: self.amount,
+            safety_deposit: self.safety_deposit,
+            timelocks: self.timelocks,
+        }
+    }
+
+    /// Los hashes guardados ya están validados
+    fn escrow_id(&self) -> String {
+        self.immutables().escrow_id().unwrap_or_else(|err| env::panic_str(err))
+    }
+
+    /// Entradas de `revealed_hashlocks` que puede llegar a ocupar: el hashlock o,
+    /// con rellenos parciales, la raíz y el hash de cada uno de sus secretos.
+    fn revealed_entries(&self) -> u16 {
+        self.parts.map_or(1, |parts| parts.saturating_add(2))
+    }
+
+    /// En cancelación pública y sin reclamar ni devolver: cualquiera puede devolverlo.
+    fn is_expired(&self) -> bool {
+        !self.claimed
+            && !self.refunded
+            && self.timelocks.current_stage(self.timestamp) == Stage::PublicCancellation
+    }
+
+    /// Reclamado hace tiempo pero sin borrar, como los de un callback que se
+    /// quedó sin gas. Las retiradas sólo se hacen antes de la cancelación, así
+    /// que pasado el margen no hay ningún pago en curso. Los devueltos no se
+    /// pueden distinguir de uno en curso.
+    fn is_abandoned(&self) -> bool {
+        self.claimed
+            && env::block_timestamp()
+                >= self.timelocks.cancellation_start(self.timestamp) + SETTLED_GRACE_PERIOD * NANOS_PER_SECOND
+    }
+
+    /// Comprueba que quien llama puede retirar en la etapa actual.
+    fn check_withdrawal_allowed(&self) -> Result<(), &'static str> {
+        match self.timelocks.current_stage(self.timestamp) {
+            Stage::Withdrawal if env::predecessor_account_id() != self.taker => {
+                Err("Sólo el taker puede reclamar en la ventana exclusiva")
+            }
+            Stage::Withdrawal | Stage::PublicWithdrawal => Ok(()),
+            _ => Err("El depósito no está en periodo de retirada"),
+        }
+    }
+
+    fn assert_withdrawal_allowed(&self) {
+        self.check_withdrawal_allowed().unwrap_or_else(|err| env::panic_str(err));
+    }
+
+    /// Comprueba que quien llama puede cancelar en la etapa actual.
+    fn check_cancellation_allowed(&self) -> Result<(), &'static str> {
+        match self.timelocks.current_stage(self.timestamp) {
+            Stage::Cancellation if env::predecessor_account_id() != self.taker => {
+                Err("Sólo el taker puede cancelar en la ventana exclusiva")
+            }
+            Stage::Cancellation | Stage::PublicCancellation => Ok(()),
+            _ => Err("El tiempo de espera aún no ha pasado"),
+        }
+    }
+}
+
+/// Coste en yoctoNEAR de lo que ha crecido el almacenamiento desde `initial_storage`.
+fn storage_cost_since(initial_storage: u64) -> u128 {
+    u128::from(env::storage_usage().saturating_sub(initial_storage)) * env::storage_byte_cost().as_yoctonear()
+}
+
+/// Cobra del NEAR adjunto el almacenamiento usado desde `initial_storage` y
+/// devuelve a `account_id` lo que sobra, más el almacenamiento liberado si
+/// ahora se usa menos.
+fn charge_attached_storage(account_id: &AccountId, initial_storage: u64) {
+    let attached = env::attached_deposit().as_yoctonear();
+    let storage_cost = storage_cost_since(initial_storage);
+    require!(attached >= storage_cost, "El NEAR adjunto no cubre el almacenamiento");
+    let freed = u128::from(initial_storage.saturating_sub(env::storage_usage())) * env::storage_byte_cost().as_yoctonear();
+    let refund = attached - storage_cost + freed;
+    if refund > 0 {
+        Promise::new(account_id.clone()).transfer(NearToken::from_yoctonear(refund));
+    }
+}
+
+fn index_insert(
+    index: &mut LookupMap<AccountId, UnorderedSet<String>>,
+    account_id: &AccountId,
+    escrow_id: &String,
+    storage_key: impl FnOnce(Vec<u8>) -> StorageKey,
+) {
+    let mut set = index
+        .get(account_id)
+        .unwrap_or_else(|| UnorderedSet::new(storage_key(env::sha256(account_id.as_bytes()))));
+    set.insert(escrow_id);
+    index.insert(account_id, &set);
+}
+
+fn index_remove(index: &mut LookupMap<AccountId, UnorderedSet<String>>, account_id: &AccountId, escrow_id: &String) {
+    let Some(mut set) = index.get(account_id) else {
+        return;
+    };
+    set.remove(escrow_id);
+    if set.is_empty() {
+        index.remove(account_id);
+    } else {
+        index.insert(account_id, &set);
+    }
 }
 
 #[near]
 impl Contract {
+    /// `default_timelocks` por defecto es `Timelocks::DEFAULT`. Marca el estado
+    /// con la versión actual.
     #[init]
     pub fn init(
-        deposit_number: U128,
+        owner_id: AccountId,
+        default_timelocks: Option<Timelocks>,
+        supported_tokens: Vec<SupportedToken>,
     ) -> Self {
+        let default_timelocks = default_timelocks.unwrap_or_default();
+        default_timelocks.validate().unwrap_or_else(|err| env::panic_str(err));
+
+        let mut tokens = UnorderedMap::new(StorageKey::SupportedTokens);
+        for token in supported_tokens {
+            token.limits.validate().unwrap_or_else(|err| env::panic_str(err));
+            tokens.insert(&token.token_id, &token.limits);
+        }
+        write_state_version();
+
         Self {
-            deposit_number,
-            deposits: UnorderedMap::new(0),
+            owner_id,
+            pending_owner_id: None,
+            paused: false,
+            default_timelocks,
+            supported_tokens: tokens,
+            stats: Stats::default(),
+            locked_value: UnorderedMap::new(StorageKey::LockedValue),
+            revealed_hashlocks: LookupMap::new(StorageKey::RevealedHashlocks),
+            near_liabilities: 0,
+            rescue_delay: DEFAULT_RESCUE_DELAY,
+            rescue_proposals: LookupMap::new(StorageKey::RescueProposals),
+            protocol_fee_bps: 0,
+            protocol_fees: UnorderedMap::new(StorageKey::ProtocolFees),
+            integrator_fees: LookupMap::new(StorageKey::IntegratorFees),
+            fees_owed: UnorderedMap::new(StorageKey::FeesOwed),
+            deposits: UnorderedMap::new(StorageKey::Deposits),
+            near_balances: LookupMap::new(StorageKey::NearBalances),
+            ft_balances: LookupMap::new(StorageKey::FtBalances),
+            ft_balances_total: LookupMap::new(StorageKey::FtBalancesTotal),
+            order_keys: LookupMap::new(StorageKey::OrderKeys),
+            nonces: LookupMap::new(StorageKey::Nonces),
+            cancelled_orders: LookupSet::new(StorageKey::CancelledOrders),
+            epochs: LookupMap::new(StorageKey::Epochs),
+            deposits_by_sender: LookupMap::new(StorageKey::DepositsBySender),
+            deposits_by_taker: LookupMap::new(StorageKey::DepositsByTaker),
+            deposits_by_token: LookupMap::new(StorageKey::DepositsByToken),
+        }
+    }
+    /// Actualiza el estado guardado tras desplegar código nuevo. Desde V1 convierte
+    /// cada depósito en su sitio (misma clave), lo indexa y rellena las
+    /// estadísticas; los ya reclamados se cuentan y se borran. `owner_id`,
+    /// `supported_tokens` y `v1_assets` son lo que V1 no guardaba: `v1_assets`
+    /// da el activo de cada depósito sin reclamar por su clave, y tiene que
+    /// estar completo. La versión del estado se lee de su etiqueta (ver
+    /// `migration`); si ya es la actual se deja como está.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate(owner_id: AccountId, supported_tokens: Vec<SupportedToken>, v1_assets: Vec<(String, Asset)>) -> Self {
+        let old = match VersionedContract::read() {
+            VersionedContract::V2(contract) => return *contract,
+            VersionedContract::V1(old) => old,
+        };
+
+        let mut contract = Self::init(owner_id, None, supported_tokens);
+        let (deposits, upgraded) = upgrade_deposits(&old.deposits, v1_assets);
+        contract.deposits = deposits;
+
+        for (key, deposit) in upgraded {
+            contract.stats.total_created += 1;
+            if deposit.claimed {
+                // Ya se pagó: sólo queda su hash, porque el secreto no se
+                // guardaba, y no hay nada que devolver al borrarlo
+                contract.revealed_hashlocks.insert(&deposit.hashlock, &deposit.timestamp);
+                contract.stats.total_claimed += 1;
+                contract.deposits.remove(&key);
+            } else {
+                contract.internal_index_deposit(&key, &deposit);
+                contract.internal_lock(&deposit.asset, deposit.amount.0);
+            }
         }
+
+        contract
     }
+
     /// Función de callback cuando se reciben tokens (NEP-141)
     #[payable]
     pub fn ft_on_transfer(
@@ -42,64 +462,290 @@ impl Contract {
         //me tengo que guardar que token es
         sender_id: AccountId,
         amount: U128,
-        msg: String, // msg debería ser el hash(secreto)
+        msg: String, // msg es un EscrowMsg en JSON
     ) -> PromiseOrValue<U128> {
-        let hash = msg;
-        assert!(
-            self.deposits.get(&hash).is_none(),
-            "Ya existe un depósito con ese hash"
-        );
+        let ft = env::predecessor_account_id();
 
-        //Falta mirar como guardar este ft en el contrato
-        //let ft = predecessor_account_id();
-        
-        //require!(ft == self.ft, "The token is not supported");
+        // Con msg vacío los tokens van al saldo del sender para sus órdenes firmadas
+        if msg.is_empty() {
+            let result = self
+                .supported_tokens
+                .get(&ft)
+                .ok_or("El token no está soportado")
+                .and_then(|_| self.internal_deposit_ft(&sender_id, &ft, amount.0));
+            return match result {
+                Ok(()) => PromiseOrValue::Value(U128(0)),
+                Err(err) => {
+                    env::log_str(err);
+                    PromiseOrValue::Value(amount)
+                }
+            };
+        }
 
-        let deposit = DepositInfo {
-            sender: sender_id,
-            amount,
-            timestamp: env::block_timestamp(),
-            claimed: false,
-        };
+        // Si el token no está en la allowlist, el importe no cumple sus límites o el msg
+        // no es válido no se entra en pánico: se devuelve todo el amount para que el
+        // token se lo reembolse al sender
+        // El almacenamiento y el depósito de seguridad salen del saldo de NEAR prepagado del sender
+        let result = self
+            .supported_tokens
+            .get(&ft)
+            .ok_or("El token no está soportado")
+            .and_then(|limits| limits.check(amount))
+            .and_then(|()| EscrowMsg::parse(&msg))
+            .and_then(|msg| msg.into_params(self.default_timelocks))
+            .and_then(|params| {
+                self.internal_create_deposit(sender_id, Asset::Ft { token_id: ft }, amount, params, NearFunding::Balance)
+            });
 
-        self.deposits.insert(&hash, &deposit);
-        PromiseOrValue::Value(U128(0))
+        match result {
+            Ok(_) => PromiseOrValue::Value(U128(0)),
+            Err(err) => {
+                env::log_str(err);
+                PromiseOrValue::Value(amount)
+            }
+        }
     }
 
-    /// Reclamar fondos proporcionando el secreto que genera el hash
-    pub fn claim_tokens(&mut self, secret: String) {
-        
-        let result = secret;
-        let hash = result;
+    /// Reclamar fondos proporcionando el secreto (en hexadecimal) cuyo hash es el
+    /// hashlock del depósito. Los fondos van siempre al `recipient` del depósito,
+    /// nunca a quien llama.
+    pub fn claim_tokens(&mut self, escrow_id: String, secret: String) -> Promise {
+        let payout = self.internal_claim(escrow_id, &secret).unwrap_or_else(|err| env::panic_str(err));
+        self.internal_payout(payout, env::predecessor_account_id())
+    }
 
-        let mut deposit = self
-            .deposits
-            .get(&hash)
-            .expect("No hay fondos asociados a ese hash");
+    /// `claim_tokens` de varios depósitos a la vez. Cada elemento se procesa por
+    /// separado: si uno falla se devuelve su error y el resto sigue adelante. Los
+    /// pagos del mismo activo al mismo recipient salen en una sola transferencia.
+    pub fn claim_many(&mut self, claims: Vec<(String, String)>) -> Vec<BatchResult> {
+        require!(claims.len() <= MAX_BATCH_SIZE, "Demasiados elementos en el lote");
 
-        assert!(!deposit.claimed, "Ya fueron reclamados");
+        let mut payouts = Vec::new();
+        let results = claims
+            .into_iter()
+            .map(|(escrow_id, secret)| {
+                let result = self.internal_claim(escrow_id.clone(), &secret).map(|payout| payouts.push(payout));
+                BatchResult::new(escrow_id, result)
+            })
+            .collect();
 
-        deposit.claimed = true;
-        self.deposits.insert(&hash, &deposit);
+        self.internal_batch_payout(payouts, env::predecessor_account_id(), false);
+        results
+    }
+
+    /// Relleno parcial de un depósito cuyo hashlock es una raíz Merkle. Se revela el
+    /// secreto `index` con su prueba y el total rellenado tras este relleno
+    /// (`fill_amount`); se libera la diferencia con lo ya rellenado.
+    pub fn claim_partial(
+        &mut self,
+        escrow_id: String,
+        secret: String,
+        index: u64,
+        proof: Vec<String>,
+        fill_amount: U128,
+    ) -> Promise {
+        let mut deposit = self.internal_get_deposit(&escrow_id)
+            .expect("No hay depósito para ese escrow_id");
+
+        deposit.assert_not_settled();
+        let parts = deposit.parts.expect("Este depósito no admite rellenos parciales");
+        deposit.assert_withdrawal_allowed();
+
+        let expected = merkle::expected_index(deposit.amount.0, deposit.filled.0, fill_amount.0, parts)
+            .unwrap_or_else(|| env::panic_str("Relleno no válido: solapa con uno anterior o excede el importe"));
+        require!(index == expected, "El índice del secreto no corresponde con el relleno");
 
-        Promise::new(env::predecessor_account_id()).transfer(NearToken::from_yoctonear(deposit.amount.0));
+        let preimage = decode_secret(&secret).unwrap_or_else(|err| env::panic_str(err));
+        let secret_hash = deposit.hash_algorithm.hash(&preimage);
+        let proof: Vec<Vec<u8>> = proof
+            .iter()
+            .map(|node| hex::decode(node).unwrap_or_else(|_| env::panic_str("La prueba debe estar en hexadecimal")))
+            .collect();
+        let root = merkle::process_proof(merkle::leaf(index, &secret_hash), &proof);
+        require!(hex::encode(root) == deposit.hashlock, "La prueba Merkle no es válida");
+
+        // Con una parte revelada la raíz tampoco se puede reutilizar
+        let root = deposit.hashlock.clone();
+        self.internal_record_hashlock(&mut deposit, &hex::encode(&secret_hash));
+        self.internal_record_hashlock(&mut deposit, &root);
+
+        let released = U128(fill_amount.0 - deposit.filled.0);
+        deposit.filled = fill_amount;
+        deposit.claimed = fill_amount == deposit.amount;
+        self.internal_save_deposit(&escrow_id, &deposit);
+
+        let (protocol_fee, integrator_fee) = deposit.fees(released.0);
+        let executor = env::predecessor_account_id();
+        let completed_by = deposit.claimed.then(|| executor.clone());
+        EscrowClaim {
+            escrow_id: &escrow_id,
+            secret: &hex::encode(&preimage),
+            recipient: &deposit.recipient,
+            executor: &executor,
+            amount: released,
+            index: Some(index),
+            protocol_fee: U128(protocol_fee),
+            integrator_fee: U128(integrator_fee),
+        }
+        .emit();
+
+        deposit
+            .asset
+            .transfer(deposit.recipient.clone(), U128(released.0 - protocol_fee - integrator_fee))
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_FILL)
+                    .resolve_fill(escrow_id, deposit, released, completed_by),
+            )
     }
 
-    /// Recuperar fondos después del timelock
-    pub fn retrieve_tokens(&mut self, hash: String) {
-        let deposit = self
-            .deposits
-            .get(&hash)
-            .expect("No hay depósito para ese hash");
+    /// Devolver los fondos al sender una vez empieza la cancelación
+    pub fn retrieve_tokens(&mut self, escrow_id: String) -> Promise {
+        let payout = self.internal_refund(escrow_id).unwrap_or_else(|err| env::panic_str(err));
+        self.internal_payout(payout, env::predecessor_account_id())
+    }
 
-        assert!(
-            env::block_timestamp() > deposit.timestamp + TIMELOCK_SECONDS * 1_000_000_000,
-            "El tiempo de espera aún no ha pasado"
-        );
-        assert!(!deposit.claimed, "Ya fueron reclamados");
+    /// `retrieve_tokens` de varios depósitos a la vez, con un resultado por
+    /// elemento como en `claim_many`.
+    pub fn refund_many(&mut self, escrow_ids: Vec<String>) -> Vec<BatchResult> {
+        require!(escrow_ids.len() <= MAX_BATCH_SIZE, "Demasiados elementos en el lote");
+
+        let mut payouts = Vec::new();
+        let results = escrow_ids
+            .into_iter()
+            .map(|escrow_id| {
+                let result = self.internal_refund(escrow_id.clone()).map(|payout| payouts.push(payout));
+                BatchResult::new(escrow_id, result)
+            })
+            .collect();
+
+        self.internal_batch_payout(payouts, env::predecessor_account_id(), false);
+        results
+    }
+
+    /// Limpieza sin permisos para que `deposits` no crezca sin límite. Mira los
+    /// `limit` escrows a partir de la posición `from_index` (0 por defecto):
+    /// devuelve a su sender los caducados (en cancelación pública) y da por
+    /// pagadas las entradas reclamadas abandonadas. Quien llama se lleva los
+    /// depósitos de seguridad de los escrows que trata, como en una cancelación
+    /// pública, y `CLEANUP_BOUNTY_BPS` del almacenamiento liberado.
+    ///
+    /// `next_index` es la posición por la que seguir, o `None` al llegar al
+    /// final. Borrar una entrada mueve la última a su hueco, así que una pasada
+    /// puede saltarse alguna; la siguiente la encuentra.
+    pub fn cleanup(&mut self, from_index: Option<U128>, limit: u32) -> CleanupResult {
+        let limit = limit as usize;
+        require!(limit > 0 && limit <= MAX_BATCH_SIZE, "El límite debe estar entre 1 y el tamaño máximo del lote");
+
+        let executor = env::predecessor_account_id();
+        let keys = self.deposits.keys_as_vector();
+        let start = from_index.map_or(0, |index| index.0.min(u128::from(keys.len())) as u64);
+        let end = keys.len().min(start + limit as u64);
+        let candidates: Vec<(String, DepositInfo)> = (start..end)
+            .filter_map(|index| keys.get(index))
+            .filter_map(|escrow_id| self.internal_get_deposit(&escrow_id).map(|deposit| (escrow_id, deposit)))
+            .filter(|(_, deposit)| deposit.is_expired() || deposit.is_abandoned())
+            .collect();
+
+        let mut result =
+            CleanupResult { next_index: (end < keys.len()).then_some(U128(u128::from(end))), ..CleanupResult::default() };
+        let mut payouts = Vec::new();
+        for (escrow_id, deposit) in candidates {
+            if deposit.is_abandoned() {
+                // Se resuelve como un pago que salió bien: se desbloquea, se
+                // cuenta y se apuntan sus comisiones
+                self.internal_resolve_payout(escrow_id.clone(), deposit, &executor, true, true);
+                result.removed.push(escrow_id);
+            } else {
+                // En cancelación pública la devolución no puede fallar
+                let payout = self.internal_refund(escrow_id.clone()).unwrap_or_else(|err| env::panic_str(err));
+                payouts.push(payout);
+                result.refunded.push(escrow_id);
+            }
+        }
+
+        self.internal_batch_payout(payouts, executor, true);
+        result
+    }
+
+    /// Callback del pago: si la transferencia falla se restaura el depósito
+    /// sin reclamar para que se pueda volver a reclamar o recuperar, y se emite
+    /// `escrow_restore` para anular el evento del pago. Si sale bien,
+    /// se borra la entrada y `executor` (quien reclamó o canceló) se lleva el
+    /// depósito de seguridad.
+    #[private]
+    pub fn resolve_payout(
+        &mut self,
+        escrow_id: String,
+        deposit: DepositInfo,
+        executor: AccountId,
+        #[callback_result] transfer_result: Result<(), PromiseError>,
+    ) -> bool {
+        self.internal_resolve_payout(escrow_id, deposit, &executor, transfer_result.is_ok(), false);
+        transfer_result.is_ok()
+    }
+
+    /// Callback de una transferencia agrupada de `claim_many`, `refund_many` o
+    /// `cleanup`: resuelve cada depósito del grupo como `resolve_payout`. Con
+    /// `with_bounty` `executor` se lleva la recompensa de `cleanup`.
+    #[private]
+    pub fn resolve_batch_payout(
+        &mut self,
+        payouts: Vec<(String, DepositInfo)>,
+        executor: AccountId,
+        with_bounty: bool,
+        #[callback_result] transfer_result: Result<(), PromiseError>,
+    ) -> bool {
+        for (escrow_id, deposit) in payouts {
+            self.internal_resolve_payout(escrow_id, deposit, &executor, transfer_result.is_ok(), with_bounty);
+        }
+        transfer_result.is_ok()
+    }
 
-        self.deposits.remove(&hash);
-        Promise::new(deposit.sender).transfer(NearToken::from_yoctonear(deposit.amount.0));
+    /// Callback de un relleno parcial. `deposit` es el depósito al rellenar; sólo
+    /// se usa para su activo, sender y comisiones. Si la transferencia falla se
+    /// descuenta `amount` de `filled` en el depósito guardado, sin tocar lo que
+    /// hayan hecho otros rellenos o una devolución mientras tanto; si el escrow
+    /// ya se cerró, el importe vuelve al sender; en los dos casos se emite
+    /// `escrow_restore`. Si el relleno completó la orden, `completed_by` se lleva
+    /// el depósito de seguridad.
+    #[private]
+    pub fn resolve_fill(
+        &mut self,
+        escrow_id: String,
+        deposit: DepositInfo,
+        amount: U128,
+        completed_by: Option<AccountId>,
+        #[callback_result] transfer_result: Result<(), PromiseError>,
+    ) -> bool {
+        let stored = self.internal_get_deposit(&escrow_id);
+
+        if transfer_result.is_ok() {
+            self.internal_unlock(&deposit.asset, amount.0);
+            self.internal_accrue_fees(&deposit, amount.0);
+            // Si después falló otro relleno, aún queda importe por liberar
+            if let (Some(executor), Some(stored)) = (completed_by, stored) {
+                if stored.filled == stored.amount {
+                    self.stats.total_claimed += 1;
+                    self.internal_remove_settled(&escrow_id, &stored, executor, false);
+                }
+            }
+            return true;
+        }
+
+        EscrowRestore { escrow_id: &escrow_id, reverted: "escrow_claim", amount }.emit();
+        match stored {
+            Some(mut stored) => {
+                stored.filled = U128(stored.filled.0 - amount.0);
+                stored.claimed = false;
+                self.internal_save_deposit(&escrow_id, &stored);
+            }
+            None => {
+                self.internal_unlock(&deposit.asset, amount.0);
+                deposit.asset.transfer(deposit.sender, amount);
+            }
+        }
+        false
     }
 
     #[payable]
@@ -107,180 +753,4390 @@ impl Contract {
         //falta el adress del sender ## ya se está guardando con el sender_id, no?
         //me tengo que guardar que token es ## en near no se puede saber qué token se está recibiendo,
         // al enviar near, por ejemplo, se llama a ft_transfer_call desde el token nep-141
-        msg: String,) -> PromiseOrValue<U128> {
+        msg: EscrowMsg,) -> PromiseOrValue<U128> {
         
         //probablemente habría que poner en algún momento la función de yoctonear por temas de seguridad
-        let hash = msg; //sigue faltando hacer el hash de la string
         let sender_id: AccountId = env::predecessor_account_id();
         let amount_near = env::attached_deposit();
+        let params = msg.into_params(self.default_timelocks).unwrap_or_else(|err| env::panic_str(err));
 
-        //el require se ejecuta todas las veces, no se si es necesario que sólo se haga la primera vez
-        require!(
-            amount_near > STORAGE_COST,
-            format!(
-                "Attach at least {} yoctoNEAR to cover for the storage cost",
-                STORAGE_COST
-            )
-        );
-        //tendría que restar el storage_cost a lo que se envía ##done en la siguiente línea
+        // El NEAR adjunto cubre el almacenamiento, el depósito de seguridad y el importe
+        let amount: U128 = U128(amount_near.as_yoctonear());
 
-        let amount: U128 = U128(amount_near.as_yoctonear() - STORAGE_COST.as_yoctonear());
+        self.internal_create_deposit(sender_id, Asset::Near, amount, params, NearFunding::Attached)
+            .unwrap_or_else(|err| env::panic_str(err));
 
-        let deposit = DepositInfo {
-            sender: sender_id,
-            amount,
-            timestamp: env::block_timestamp(),
-            claimed: false,
-        };
+        PromiseOrValue::Value(U128(0))
+    }
 
-        self.deposits.insert(&hash, &deposit);
+    /// Añade el NEAR adjunto al saldo de `account_id` (por defecto quien llama),
+    /// que paga el almacenamiento y los depósitos de seguridad de los escrows NEP-141.
+    /// La primera vez se descuenta el almacenamiento de la propia entrada del saldo.
+    #[payable]
+    pub fn deposit_near(&mut self, account_id: Option<AccountId>) -> U128 {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let balance = self.near_balances.get(&account_id).unwrap_or(0) + env::attached_deposit().as_yoctonear();
 
-        PromiseOrValue::Value(U128(0))
+        let initial_storage = env::storage_usage();
+        self.internal_set_near_balance(&account_id, balance);
+        let storage_cost = storage_cost_since(initial_storage);
+        require!(balance > storage_cost, "El NEAR adjunto no cubre el almacenamiento");
+
+        self.internal_set_near_balance(&account_id, balance - storage_cost);
+        self.near_liabilities += env::attached_deposit().as_yoctonear() - storage_cost;
+        U128(balance - storage_cost)
     }
 
-    //TODO: hacer una función que recibe near y que lo guarde en depositinfo (amount)
-    //hacer hash
-    //testear todo bien
-    //revisar las funciones que ha hecho el chatgpt
+    /// Retira NEAR del saldo prepagado de quien llama. Requiere 1 yoctoNEAR.
+    #[payable]
+    pub fn withdraw_near(&mut self, amount: U128) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let balance = self.near_balances.get(&account_id).unwrap_or(0);
+        require!(amount.0 <= balance, "Saldo de NEAR insuficiente");
+
+        self.internal_set_near_balance(&account_id, balance - amount.0);
+        self.near_liabilities -= amount.0;
+        Promise::new(account_id).transfer(NearToken::from_yoctonear(amount.0))
+    }
 
-    pub fn claim_near(){
+    pub fn get_near_balance(&self, account_id: AccountId) -> U128 {
+        U128(self.near_balances.get(&account_id).unwrap_or(0))
+    }
+
+    /// Retira tokens del saldo de quien llama. Si la transferencia falla se
+    /// vuelven a sumar en `resolve_ft_withdrawal`. Requiere 1 yoctoNEAR.
+    #[payable]
+    pub fn withdraw_ft(&mut self, token_id: AccountId, amount: U128) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.internal_debit_ft(&account_id, &token_id, amount.0).unwrap_or_else(|err| env::panic_str(err));
 
+        Asset::Ft { token_id: token_id.clone() }.transfer(account_id.clone(), amount).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_FT_WITHDRAWAL)
+                .resolve_ft_withdrawal(account_id, token_id, amount),
+        )
     }
 
-    pub fn get_deposit_info(&self, string: String) -> Option<DepositInfo>{
-        self.deposits.get(&string)
+    #[private]
+    pub fn resolve_ft_withdrawal(
+        &mut self,
+        account_id: AccountId,
+        token_id: AccountId,
+        amount: U128,
+        #[callback_result] transfer_result: Result<(), PromiseError>,
+    ) -> bool {
+        if transfer_result.is_err() {
+            self.internal_credit_ft(&account_id, &token_id, amount.0);
+        }
+        transfer_result.is_ok()
     }
 
-    pub fn get_deposit_number(&self) -> U128{
-        self.deposit_number
+    /// Tokens de `token_id` depositados por `account_id` para sus órdenes firmadas
+    pub fn get_ft_balance(&self, account_id: AccountId, token_id: AccountId) -> U128 {
+        U128(self.ft_balances.get(&(account_id, token_id)).unwrap_or(0))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use near_sdk::testing_env;
-    use near_sdk::test_utils::VMContextBuilder;
+    /// Registra (o borra con `None`) la clave ed25519 con la que quien llama
+    /// firma sus órdenes. El NEAR adjunto paga el almacenamiento y se devuelve
+    /// lo que sobra junto con el almacenamiento liberado al borrarla.
+    #[payable]
+    pub fn set_order_key(&mut self, public_key: Option<PublicKey>) {
+        require!(!env::attached_deposit().is_zero(), "Hay que adjuntar al menos 1 yoctoNEAR");
+        let account_id = env::predecessor_account_id();
 
-    use super::*;
+        let initial_storage = env::storage_usage();
+        match public_key {
+            Some(public_key) => {
+                require!(public_key.curve_type() == CurveType::ED25519, "La clave de las órdenes debe ser ed25519");
+                let key: [u8; 32] = public_key.as_bytes()[1..].try_into().unwrap_or_else(|_| env::abort());
+                self.order_keys.insert(&account_id, &key);
+            }
+            None => {
+                self.order_keys.remove(&account_id);
+            }
+        }
 
-    #[test]
-    fn init_contract() {
-        let contract = Contract::init(
-            U128(3),
+        charge_attached_storage(&account_id, initial_storage);
+    }
+
+    pub fn get_order_key(&self, account_id: AccountId) -> Option<PublicKey> {
+        let key = self.order_keys.get(&account_id)?;
+        PublicKey::from_parts(CurveType::ED25519, key.to_vec()).ok()
+    }
+
+    /// Rellena una orden firmada por el maker: comprueba la firma con la clave
+    /// registrada, la caducidad y el nonce, y crea el escrow por cuenta del maker
+    /// con quien llama como taker. El importe sale del saldo de tokens del maker
+    /// y el almacenamiento, de su saldo de NEAR. El resolver adjunta justo el
+    /// depósito de seguridad de la orden, que queda apuntado a su nombre en
+    /// `safety_deposit_payer`. `dst_amount` es lo que ofrece el taker en destino;
+    /// no puede quedar por debajo de la subasta y por defecto es su importe actual.
+    #[payable]
+    pub fn fill_order(&mut self, order: Order, signature: Base64VecU8, dst_amount: Option<U128>) -> String {
+        require!(
+            env::attached_deposit().as_yoctonear() == order.safety_deposit.0,
+            "Hay que adjuntar el depósito de seguridad de la orden"
         );
+        let current_dst_amount = self.internal_check_order(&order, &signature).unwrap_or_else(|err| env::panic_str(err));
+        let dst_amount = dst_amount.unwrap_or(U128(current_dst_amount));
+        require!(dst_amount.0 >= current_dst_amount, "El importe ofrecido está por debajo de la subasta");
 
-        let deposit_number = contract.get_deposit_number();
+        self.internal_use_nonce(&order.maker, order.nonce);
+        self.internal_debit_ft(&order.maker, &order.token, order.amount.0)
+            .unwrap_or_else(|err| env::panic_str(err));
 
-        assert_eq!(deposit_number, U128(3));
+        let taker = env::predecessor_account_id();
+        let order_hash = order.hash();
+        let escrow_id = order
+            .escrow_msg(taker.clone(), dst_amount)
+            .into_params(self.default_timelocks)
+            .and_then(|params| {
+                let asset = Asset::Ft { token_id: order.token.clone() };
+                let funding = NearFunding::Resolver(taker.clone());
+                self.internal_create_deposit(order.maker.clone(), asset, order.amount, params, funding)
+            })
+            .unwrap_or_else(|err| env::panic_str(err));
+
+        OrderFilled { order_hash: &order_hash, maker: &order.maker, taker: &taker, nonce: order.nonce, escrow_id: &escrow_id }
+            .emit();
+        escrow_id
     }
 
-      #[test]
-    fn test_on_transfer() {
-        let mut contract = Contract::init(
-            U128(3),
-        );
+    /// `order_hash` de una orden, que es lo que la identifica en el escrow
+    pub fn get_order_hash(&self, order: Order) -> String {
+        order.hash()
+    }
 
-        let alice: AccountId = "alice.near".parse().unwrap();
+    /// Importe mínimo en destino con el que se puede rellenar ahora la orden
+    pub fn get_order_dst_amount(&self, order: Order) -> U128 {
+        U128(order.current_dst_amount().unwrap_or_else(|err| env::panic_str(err)))
+    }
 
-        contract.ft_on_transfer(alice.clone(), U128(23), "asdasd".to_string());
+    /// Por qué no se puede rellenar ahora la orden, o `None` si se puede. No
+    /// comprueba el saldo de NEAR del maker para el almacenamiento y el
+    /// depósito de seguridad.
+    pub fn get_order_error(&self, order: Order, signature: Base64VecU8) -> Option<String> {
+        self.internal_check_order(&order, &signature).err().map(String::from)
+    }
 
-        let value = contract.deposits.get(&"asdasd".to_string()).unwrap();
+    pub fn is_order_fillable(&self, order: Order, signature: Base64VecU8) -> bool {
+        self.internal_check_order(&order, &signature).is_ok()
+    }
 
-        assert_eq!(value.sender, alice);
-        assert_eq!(value.amount, U128(23));
-        assert_eq!(value.claimed, false);
-        assert_eq!(value.timestamp, env::block_timestamp());
+    /// Cancela una orden de quien llama. El NEAR adjunto paga el almacenamiento
+    /// y se devuelve lo que sobra.
+    #[payable]
+    pub fn cancel_order(&mut self, order_hash: String) {
+        require!(!env::attached_deposit().is_zero(), "Hay que adjuntar al menos 1 yoctoNEAR");
+        let maker = env::predecessor_account_id();
+        let order_hash =
+            normalize_bytes32(&order_hash).unwrap_or_else(|| env::panic_str("El order_hash debe ser de 32 bytes en hexadecimal"));
+
+        let initial_storage = env::storage_usage();
+        require!(self.cancelled_orders.insert(&(maker.clone(), order_hash.clone())), "La orden ya está cancelada");
+        charge_attached_storage(&maker, initial_storage);
+
+        OrderCancelled { maker: &maker, order_hash: &order_hash }.emit();
     }
 
-    //este test no va aquí, hay que hacer un test de integración
-     #[test]
-    fn recive_near() {
-        let mut contract = Contract::init(
-            U128(3),
-        );
+    /// Marca como usados los nonces de `bitmap` en la serie `series` (el bit
+    /// `i` es el nonce `series * 128 + i`). El NEAR adjunto paga el
+    /// almacenamiento y se devuelve lo que sobra.
+    #[payable]
+    pub fn invalidate_nonces(&mut self, series: u64, bitmap: U128) {
+        require!(!env::attached_deposit().is_zero(), "Hay que adjuntar al menos 1 yoctoNEAR");
+        require!(bitmap.0 != 0, "El bitmap no invalida ningún nonce");
+        let maker = env::predecessor_account_id();
 
-        let alice: AccountId = "alice.near".parse().unwrap();
+        let initial_storage = env::storage_usage();
+        let key = (maker.clone(), series);
+        let bitmap = self.nonces.get(&key).unwrap_or(0) | bitmap.0;
+        self.nonces.insert(&key, &bitmap);
+        charge_attached_storage(&maker, initial_storage);
 
-        let mut builder = VMContextBuilder::new();
-        builder
-            .attached_deposit(NearToken::from_near(1))
-            .predecessor_account_id(alice.clone());
+        NoncesInvalidated { maker: &maker, series, bitmap: U128(bitmap) }.emit();
+    }
 
-        testing_env!(builder.build());
+    /// Pasa a la siguiente época de quien llama, con lo que deja sin validez
+    /// todas las órdenes que firmó antes. El NEAR adjunto paga el
+    /// almacenamiento y se devuelve lo que sobra.
+    #[payable]
+    pub fn increase_epoch(&mut self) -> u64 {
+        require!(!env::attached_deposit().is_zero(), "Hay que adjuntar al menos 1 yoctoNEAR");
+        let maker = env::predecessor_account_id();
 
-        contract.recive_near("asdasd".to_string());
-  
-        let value = contract.deposits.get(&"asdasd".to_string()).unwrap();
+        let initial_storage = env::storage_usage();
+        let epoch = self.epochs.get(&maker).unwrap_or(0) + 1;
+        self.epochs.insert(&maker, &epoch);
+        charge_attached_storage(&maker, initial_storage);
 
-        //println!("{:?}", value);
-        let attached_deposit = NearToken::from_near(1).checked_sub(STORAGE_COST).unwrap().as_yoctonear();
+        EpochIncreased { maker: &maker, epoch }.emit();
+        epoch
+    }
 
-        assert_eq!(value.sender, alice);
-        assert_eq!(value.amount, attached_deposit.into());
-        assert_eq!(value.claimed, false);
-        assert_eq!(value.timestamp, env::block_timestamp());
+    pub fn get_epoch(&self, maker: AccountId) -> u64 {
+        self.epochs.get(&maker).unwrap_or(0)
     }
 
-    #[test]
-    fn claim_tokens(){
-        let mut contract = Contract::init(
-            U128(3),
-        );
+    pub fn is_order_cancelled(&self, maker: AccountId, order_hash: String) -> bool {
+        normalize_bytes32(&order_hash).is_some_and(|order_hash| self.cancelled_orders.contains(&(maker, order_hash)))
+    }
 
-        let alice: AccountId = "alice.near".parse().unwrap();
-        let hash = "hash123".to_string();
+    pub fn is_nonce_used(&self, maker: AccountId, nonce: u64) -> bool {
+        let (series, bit) = nonce_slot(nonce);
+        self.nonces.get(&(maker, series)).unwrap_or(0) & bit != 0
+    }
 
-        let mut builder = VMContextBuilder::new();
-        builder
-            .attached_deposit(NearToken::from_near(1))
-            .predecessor_account_id(alice.clone());
+    //testear todo bien
+    //revisar las funciones que ha hecho el chatgpt
 
-        testing_env!(builder.build());
+    pub fn get_deposit_info(&self, escrow_id: String) -> Option<DepositInfo>{
+        self.internal_get_deposit(&escrow_id)
+    }
 
-        let deposit_info = DepositInfo{
-            sender: alice.clone(),
-            amount: U128::from(1_000_000_000_000_000_000_000_000),
-            claimed: false,
-            timestamp: env::block_timestamp(),
-        };
+    /// escrow_id que tendrá un depósito con estos `Immutables`, para que las
+    /// herramientas off-chain y el `EscrowFactory` de EVM calculen el mismo.
+    pub fn get_escrow_id(&self, immutables: Immutables) -> String {
+        immutables.escrow_id().unwrap_or_else(|err| env::panic_str(err))
+    }
 
-        contract.deposits.insert(&hash, &deposit_info);
-        contract.claim_tokens(hash.clone());
+    /// Momento (nanosegundos) en que se reveló el secreto de `hashlock`, si ya se
+    /// reveló. Un escrow con ese hashlock se rechaza.
+    pub fn get_hashlock_revealed_at(&self, hashlock: String) -> Option<U64> {
+        let hashlock = normalize_bytes32(&hashlock)?;
+        self.revealed_hashlocks.get(&hashlock).map(U64)
+    }
 
-        let updated_deposit: DepositInfo = contract.deposits.get(&hash).unwrap();
+    pub fn get_stats(&self) -> StatsView {
+        StatsView {
+            stats: self.stats.clone(),
+            locked: self
+                .locked_value
+                .iter()
+                .map(|(asset, amount)| LockedValue { asset, amount: U128(amount) })
+                .collect(),
+        }
+    }
 
-        assert!(updated_deposit.claimed, "Deposit has not been claimed yet");
+    pub fn get_config(&self) -> ConfigView {
+        ConfigView {
+            owner_id: self.owner_id.clone(),
+            pending_owner_id: self.pending_owner_id.clone(),
+            paused: self.paused,
+            default_timelocks: self.default_timelocks,
+            supported_tokens: self
+                .supported_tokens
+                .iter()
+                .map(|(token_id, limits)| SupportedToken { token_id, limits })
+                .collect(),
+            rescue_delay: self.rescue_delay,
+            protocol_fee_bps: self.protocol_fee_bps,
+        }
     }
 
-    #[test]
-    fn retrieve_tokens() {
-         let mut contract = Contract::init(
-            U128(3),
+    /// Añade un token a la allowlist o cambia sus límites. Sólo el owner; requiere 1 yoctoNEAR.
+    #[payable]
+    pub fn set_supported_token(&mut self, token_id: AccountId, min_amount: Option<U128>, max_amount: Option<U128>) {
+        assert_one_yocto();
+        self.assert_owner();
+
+        let limits = TokenLimits { min_amount: min_amount.unwrap_or(U128(0)), max_amount };
+        limits.validate().unwrap_or_else(|err| env::panic_str(err));
+        self.supported_tokens.insert(&token_id, &limits);
+
+        TokenSet { owner: &self.owner_id, token_id: &token_id, min_amount: limits.min_amount, max_amount }.emit();
+    }
+
+    /// Quita un token de la allowlist. Los escrows ya creados se pueden seguir
+    /// reclamando y devolviendo. Sólo el owner; requiere 1 yoctoNEAR.
+    #[payable]
+    pub fn remove_supported_token(&mut self, token_id: AccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+
+        require!(self.supported_tokens.remove(&token_id).is_some(), "El token no está soportado");
+
+        TokenRemoved { owner: &self.owner_id, token_id: &token_id }.emit();
+    }
+
+    /// Cambia el tiempo que tiene que pasar desde que se propone un rescate hasta
+    /// que se puede ejecutar, también para los ya propuestos. Sólo el owner;
+    /// requiere 1 yoctoNEAR.
+    #[payable]
+    pub fn set_rescue_delay(&mut self, rescue_delay: u32) {
+        assert_one_yocto();
+        self.assert_owner();
+
+        self.rescue_delay = rescue_delay;
+        RescueDelaySet { owner: &self.owner_id, rescue_delay }.emit();
+    }
+
+    /// Primer paso para rescatar fondos enviados al contrato por error, como
+    /// `rescueFunds` de los escrows de Fusion+: propone rescatar `amount` de
+    /// `asset`, que se podrá ejecutar con `execute_rescue` pasado `rescue_delay`.
+    /// Sustituye la propuesta anterior del mismo activo y vuelve a empezar la
+    /// espera, salvo si se está ejecutando. Sólo el owner; requiere 1 yoctoNEAR.
+    #[payable]
+    pub fn propose_rescue(&mut self, asset: Asset, amount: U128) {
+        assert_one_yocto();
+        self.assert_owner();
+        require!(amount.0 > 0, "El importe del rescate no puede ser 0");
+        require!(
+            !self.rescue_proposals.get(&asset).is_some_and(|proposal| proposal.executing),
+            "Ya hay un rescate en curso de ese activo"
         );
 
-        let alice: AccountId = "alice.near".parse().unwrap();
-        let hash = "hash123".to_string();
+        let proposal = RescueProposal { amount, proposed_at: env::block_timestamp(), executing: false };
+        self.rescue_proposals.insert(&asset, &proposal);
+        RescueProposed { owner: &self.owner_id, asset: &asset, amount }.emit();
+    }
 
-        let deposit_info = DepositInfo{
-            sender: alice.clone(),
-            amount: U128::from(1_000_000_000_000_000_000_000_000),
-            claimed: false,
-            timestamp: env::block_timestamp() + 25 * 3600 * 1_000_000_000,
+    /// Ejecuta el rescate propuesto de `asset` una vez pasado `rescue_delay`.
+    /// Sólo se mueve el excedente por encima de lo bloqueado en escrows activos,
+    /// las comisiones y los saldos, comprobado ahora y no al proponerlo. Los
+    /// fondos van al owner. Sólo el owner; requiere 1 yoctoNEAR.
+    ///
+    /// Para NEP-141 el saldo se consulta con `ft_balance_of` y la comprobación se
+    /// hace en `resolve_rescue`. Mientras tanto la propuesta queda marcada como
+    /// en ejecución y no se puede volver a ejecutar ni sustituir.
+    #[payable]
+    pub fn execute_rescue(&mut self, asset: Asset) -> Promise {
+        assert_one_yocto();
+        self.assert_owner();
+        let mut proposal = self
+            .rescue_proposals
+            .get(&asset)
+            .unwrap_or_else(|| env::panic_str("No hay ningún rescate propuesto para ese activo"));
+        require!(!proposal.executing, "Ya hay un rescate en curso de ese activo");
+        require!(proposal.is_ready(self.rescue_delay, env::block_timestamp()), "El periodo de rescate aún no ha pasado");
+
+        let executor = env::predecessor_account_id();
+        match &asset {
+            Asset::Near => {
+                require!(
+                    proposal.amount.0 <= self.internal_near_surplus(),
+                    "Sólo se puede rescatar el excedente de los escrows activos"
+                );
+                // El NEAR transferido sale del saldo en esta misma ejecución
+                self.rescue_proposals.remove(&asset);
+                FundsRescued { executor: &executor, asset: &asset, amount: proposal.amount }.emit();
+                asset.transfer(executor, proposal.amount)
+            }
+            Asset::Ft { token_id } => {
+                proposal.executing = true;
+                self.rescue_proposals.insert(&asset, &proposal);
+
+                ext_ft_core::ext(token_id.clone())
+                    .with_static_gas(GAS_FOR_FT_BALANCE_OF)
+                    .ft_balance_of(env::current_account_id())
+                    .then(
+                        Self::ext(env::current_account_id())
+                            .with_static_gas(GAS_FOR_RESOLVE_RESCUE)
+                            .resolve_rescue(asset, executor, proposal.amount),
+                    )
+            }
+        }
+    }
+
+    /// Callback de `ft_balance_of` en `execute_rescue`: transfiere si el saldo
+    /// del contrato cubre lo que guarda para otros más `amount`. Si no, la
+    /// propuesta deja de estar en ejecución sin entrar en pánico, que la dejaría
+    /// bloqueada, y se puede volver a ejecutar o sustituir.
+    #[private]
+    pub fn resolve_rescue(
+        &mut self,
+        asset: Asset,
+        executor: AccountId,
+        amount: U128,
+        #[callback_result] balance: Result<U128, PromiseError>,
+    ) -> PromiseOrValue<bool> {
+        let surplus = balance.map(|balance| balance.0.saturating_sub(self.internal_held(&asset)));
+        let error = match surplus {
+            Ok(surplus) if amount.0 <= surplus => None,
+            Ok(_) => Some("Sólo se puede rescatar el excedente de los escrows activos"),
+            Err(_) => Some("No se pudo consultar el saldo del token"),
         };
+        if let Some(error) = error {
+            env::log_str(error);
+            if let Some(mut proposal) = self.rescue_proposals.get(&asset) {
+                proposal.executing = false;
+                self.rescue_proposals.insert(&asset, &proposal);
+            }
+            return PromiseOrValue::Value(false);
+        }
 
-        let mut builder = VMContextBuilder::new();
-        builder
-            .attached_deposit(NearToken::from_near(1))
-            .predecessor_account_id(alice.clone());
+        FundsRescued { executor: &executor, asset: &asset, amount }.emit();
+        PromiseOrValue::Promise(
+            asset.transfer(executor, amount).then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_RESCUE_TRANSFER)
+                    .resolve_rescue_transfer(asset),
+            ),
+        )
+    }
 
-        testing_env!(builder.build());
+    /// Borra la propuesta ejecutada cuando termina la transferencia.
+    #[private]
+    pub fn resolve_rescue_transfer(
+        &mut self,
+        asset: Asset,
+        #[callback_result] transfer_result: Result<(), PromiseError>,
+    ) -> bool {
+        self.rescue_proposals.remove(&asset);
+        transfer_result.is_ok()
+    }
+
+    pub fn get_rescue_proposal(&self, asset: Asset) -> Option<RescueProposal> {
+        self.rescue_proposals.get(&asset)
+    }
 
-        contract.deposits.insert(&hash, &deposit_info);
+    /// Cambia la comisión del protocolo de los escrows que se creen a partir de
+    /// ahora. Sólo el owner; requiere 1 yoctoNEAR.
+    #[payable]
+    pub fn set_protocol_fee(&mut self, protocol_fee_bps: u16) {
+        assert_one_yocto();
+        self.assert_owner();
+        require!(protocol_fee_bps <= MAX_FEE_BPS, "La comisión del protocolo supera el máximo");
 
-        contract.retrieve_tokens(hash.clone());
+        self.protocol_fee_bps = protocol_fee_bps;
+        ProtocolFeeSet { owner: &self.owner_id, protocol_fee_bps }.emit();
+    }
 
-        assert!(contract.deposits.get(&hash).is_none(),"Deposit was not deleted after retrieving the tokens");
+    /// Envía al owner `amount` de las comisiones del protocolo en `token`.
+    /// Sólo el owner; requiere 1 yoctoNEAR.
+    #[payable]
+    pub fn withdraw_fees(&mut self, token: Asset, amount: U128) -> Promise {
+        assert_one_yocto();
+        self.assert_owner();
+        self.internal_withdraw_fees(None, self.owner_id.clone(), token, amount)
     }
 
-    //TODO: hacer el test del flow del contrato
-}
\ No newline at end of file
+    /// Envía a quien llama `amount` de sus comisiones de integrador en `token`.
+    /// Requiere 1 yoctoNEAR.
+    #[payable]
+    pub fn withdraw_integrator_fees(&mut self, token: Asset, amount: U128) -> Promise {
+        assert_one_yocto();
+        let integrator = env::predecessor_account_id();
+        self.internal_withdraw_fees(Some(integrator.clone()), integrator, token, amount)
+    }
+
+    /// Callback de una retirada de comisiones: si la transferencia falla se
+    /// vuelven a apuntar en su libro.
+    #[private]
+    pub fn resolve_fee_withdrawal(
+        &mut self,
+        integrator: Option<AccountId>,
+        token: Asset,
+        amount: U128,
+        #[callback_result] transfer_result: Result<(), PromiseError>,
+    ) -> bool {
+        if transfer_result.is_ok() {
+            return true;
+        }
+
+        self.internal_credit_fees(integrator.as_ref(), &token, amount.0);
+        false
+    }
+
+    /// Comisiones del protocolo acumuladas por activo
+    pub fn get_protocol_fees(&self) -> Vec<FeeBalance> {
+        fees::balances(&self.protocol_fees)
+    }
+
+    /// Comisiones acumuladas de un integrador por activo
+    pub fn get_integrator_fees(&self, account_id: AccountId) -> Vec<FeeBalance> {
+        self.integrator_fees.get(&account_id).map_or_else(Vec::new, |ledger| fees::balances(&ledger))
+    }
+
+    /// Registra a `account_id` (por defecto quien llama) como integrador de
+    /// `token`, como `storage_deposit` de NEP-145: el NEAR adjunto paga la
+    /// entrada de su libro de comisiones y se devuelve lo que sobra. Los escrows
+    /// sólo aceptan comisiones de integradores registrados para su activo.
+    #[payable]
+    pub fn register_integrator(&mut self, account_id: Option<AccountId>, token: Asset) {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let initial_storage = env::storage_usage();
+        let mut ledger = self.integrator_fees.get(&account_id).unwrap_or_else(|| {
+            UnorderedMap::new(StorageKey::IntegratorFeesInner { account_hash: env::sha256(account_id.as_bytes()) })
+        });
+        if fees::register(&mut ledger, &token) {
+            self.integrator_fees.insert(&account_id, &ledger);
+        }
+        charge_attached_storage(&env::predecessor_account_id(), initial_storage);
+    }
+
+    pub fn is_integrator_registered(&self, account_id: AccountId, token: Asset) -> bool {
+        self.internal_integrator_registered(&account_id, &token)
+    }
+
+    /// Primer paso para cambiar de owner: `new_owner_id` tiene que llamar a
+    /// `accept_ownership`. Sólo el owner; requiere 1 yoctoNEAR.
+    #[payable]
+    pub fn propose_owner(&mut self, new_owner_id: AccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+
+        OwnershipProposed { owner: &self.owner_id, new_owner: &new_owner_id }.emit();
+        self.pending_owner_id = Some(new_owner_id);
+    }
+
+    /// El owner propuesto acepta la propiedad del contrato. Requiere 1 yoctoNEAR.
+    #[payable]
+    pub fn accept_ownership(&mut self) {
+        assert_one_yocto();
+        let new_owner_id = env::predecessor_account_id();
+        require!(
+            self.pending_owner_id.as_ref() == Some(&new_owner_id),
+            "Sólo el owner propuesto puede aceptar"
+        );
+
+        OwnershipTransferred { old_owner: &self.owner_id, new_owner: &new_owner_id }.emit();
+        self.owner_id = new_owner_id;
+        self.pending_owner_id = None;
+    }
+
+    /// Bloquea los depósitos nuevos. Sólo el owner; requiere 1 yoctoNEAR.
+    #[payable]
+    pub fn pause(&mut self) {
+        self.internal_set_paused(true);
+    }
+
+    /// Sólo el owner; requiere 1 yoctoNEAR.
+    #[payable]
+    pub fn unpause(&mut self) {
+        self.internal_set_paused(false);
+    }
+
+    /// Depósitos en el estado `status_filter`, o todos con `None`. Se recorren
+    /// desde la posición `from_index` (0 por defecto) hasta reunir `limit`;
+    /// `next_index` es la posición por la que seguir, o `None` al llegar al final.
+    pub fn get_deposits(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+        status_filter: Option<EscrowStatus>,
+    ) -> DepositsPage {
+        let limit = limit.unwrap_or(u64::MAX);
+        require!(limit != 0, "El límite no puede ser 0");
+        let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+        let start = usize::try_from(from_index.map_or(0, |index| index.0)).unwrap_or(usize::MAX);
+
+        let mut page = DepositsPage { deposits: Vec::new(), next_index: None };
+        for (index, (escrow_id, deposit)) in self.deposits.iter().enumerate().skip(start) {
+            if page.deposits.len() == limit {
+                page.next_index = Some(U128(index as u128));
+                break;
+            }
+            let deposit = DepositInfo::from(deposit);
+            let status = deposit.status();
+            if status_filter.is_none_or(|filter| status == filter) {
+                page.deposits.push(DepositView { escrow_id, status, deposit });
+            }
+        }
+        page
+    }
+
+    pub fn get_deposits_by_sender(&self, account_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<DepositView> {
+        self.views_of(paginate_set(self.deposits_by_sender.get(&account_id), from_index, limit))
+    }
+
+    pub fn get_deposits_by_taker(&self, account_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<DepositView> {
+        self.views_of(paginate_set(self.deposits_by_taker.get(&account_id), from_index, limit))
+    }
+
+    /// Depósitos NEP-141 de `token_id`
+    pub fn get_deposits_by_token(&self, token_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<DepositView> {
+        self.views_of(paginate_set(self.deposits_by_token.get(&token_id), from_index, limit))
+    }
+}
+
+impl Contract {
+    /// Crea el depósito indexado por su escrow_id. El almacenamiento se mide antes y
+    /// después de insertarlo y, junto con el de los hashes que se revelarán y el
+    /// depósito de seguridad, se cobra al sender según `funding`.
+    fn internal_create_deposit(
+        &mut self,
+        sender: AccountId,
+        asset: Asset,
+        amount: U128,
+        params: EscrowParams,
+        funding: NearFunding,
+    ) -> Result<String, &'static str> {
+        if self.paused {
+            return Err("El contrato está pausado");
+        }
+        if self.revealed_hashlocks.contains_key(&params.hashlock) {
+            return Err("El secreto de ese hashlock ya se reveló en otro escrow");
+        }
+        if let Some(fee) = &params.integrator_fee {
+            if !self.internal_integrator_registered(&fee.recipient, &asset) {
+                return Err("El integrador no está registrado para ese activo");
+            }
+        }
+
+        let mut deposit = DepositInfo {
+            sender,
+            amount,
+            timestamp: env::block_timestamp(),
+            claimed: false,
+            hashlock: params.hashlock,
+            hash_algorithm: params.hash_algorithm,
+            asset,
+            taker: params.taker,
+            timelocks: params.timelocks,
+            recipient: params.recipient,
+            order_hash: params.order_hash,
+            src_chain_id: params.src_chain_id,
+            dst_chain_id: params.dst_chain_id,
+            evm_maker: params.evm_maker,
+            dst_token: params.dst_token,
+            dst_amount: params.dst_amount,
+            safety_deposit: params.safety_deposit,
+            safety_deposit_payer: None,
+            parts: params.parts,
+            filled: U128(0),
+            refunded: false,
+            storage_deposit: U128(0),
+            revealed_deposit: U128(0),
+            protocol_fee_bps: self.protocol_fee_bps,
+            integrator_fee: params.integrator_fee,
+        };
+
+        // Se mide el almacenamiento (incluidos los índices) con el importe bruto; el
+        // escrow_id definitivo ocupa lo mismo
+        let provisional_id = deposit.escrow_id();
+        if self.internal_get_deposit(&provisional_id).is_some() {
+            return Err("Ya existe un depósito con ese escrow_id");
+        }
+        let initial_storage = env::storage_usage();
+        self.internal_save_deposit(&provisional_id, &deposit);
+        self.internal_index_deposit(&provisional_id, &deposit);
+        let storage_cost = storage_cost_since(initial_storage);
+        self.internal_remove_deposit(&provisional_id, &deposit);
+
+        // Los hashes que se revelen se guardan para siempre: se cobran aparte, en
+        // `revealed_deposit`, y se siguen debiendo hasta que se guardan
+        let revealed_cost = self.internal_revealed_entry_cost() * u128::from(deposit.revealed_entries());
+        let reserved = storage_cost + revealed_cost + deposit.safety_deposit.0;
+        let remaining_balance = match funding {
+            NearFunding::Attached => {
+                match deposit.amount.0.checked_sub(reserved) {
+                    Some(rest) if rest > 0 => deposit.amount = U128(rest),
+                    _ => return Err("Attach enough yoctoNEAR to cover for the storage cost and the safety deposit"),
+                }
+                None
+            }
+            NearFunding::Balance => {
+                let balance = self.near_balances.get(&deposit.sender).unwrap_or(0);
+                let remaining = balance
+                    .checked_sub(reserved)
+                    .ok_or("Saldo de NEAR insuficiente para el almacenamiento y el depósito de seguridad")?;
+                Some(remaining)
+            }
+            NearFunding::Resolver(resolver) => {
+                let balance = self.near_balances.get(&deposit.sender).unwrap_or(0);
+                let remaining = balance
+                    .checked_sub(reserved - deposit.safety_deposit.0)
+                    .ok_or("Saldo de NEAR insuficiente para el almacenamiento")?;
+                deposit.safety_deposit_payer = Some(resolver);
+                Some(remaining)
+            }
+        };
+
+        let escrow_id = deposit.escrow_id();
+        if self.internal_get_deposit(&escrow_id).is_some() {
+            return Err("Ya existe un depósito con ese escrow_id");
+        }
+        // El almacenamiento pasa a cubrirlo el stake; el depósito de seguridad y
+        // el prepago de los hashes revelados se siguen debiendo
+        match remaining_balance {
+            Some(remaining) => {
+                self.internal_set_near_balance(&deposit.sender, remaining);
+                self.near_liabilities -= storage_cost;
+            }
+            None => self.near_liabilities += deposit.safety_deposit.0 + revealed_cost,
+        }
+        // El del resolver llega adjunto y no estaba en ningún saldo
+        if deposit.safety_deposit_payer.is_some() {
+            self.near_liabilities += deposit.safety_deposit.0;
+        }
+
+        deposit.storage_deposit = U128(storage_cost);
+        deposit.revealed_deposit = U128(revealed_cost);
+        self.internal_save_deposit(&escrow_id, &deposit);
+        self.internal_index_deposit(&escrow_id, &deposit);
+
+        self.stats.total_created += 1;
+        self.internal_lock(&deposit.asset, deposit.amount.0);
+
+        EscrowCreate {
+            escrow_id: &escrow_id,
+            hashlock: &deposit.hashlock,
+            sender: &deposit.sender,
+            taker: &deposit.taker,
+            recipient: &deposit.recipient,
+            asset: &deposit.asset,
+            amount: deposit.amount,
+            safety_deposit: deposit.safety_deposit,
+            order_hash: &deposit.order_hash,
+            src_chain_id: deposit.src_chain_id,
+            dst_chain_id: deposit.dst_chain_id,
+            evm_maker: deposit.evm_maker,
+            dst_token: deposit.dst_token,
+            dst_amount: deposit.dst_amount,
+            parts: deposit.parts,
+        }
+        .emit();
+
+        Ok(escrow_id)
+    }
+
+    /// Borra un depósito ya pagado: devuelve al sender el almacenamiento y lo
+    /// que no se gastó del prepago de los hashes revelados, y da el depósito de
+    /// seguridad a `executor`. Con `with_bounty` (desde `cleanup`) `executor` se
+    /// lleva además parte del almacenamiento.
+    fn internal_remove_settled(&mut self, escrow_id: &String, deposit: &DepositInfo, executor: AccountId, with_bounty: bool) {
+        self.internal_remove_deposit(escrow_id, deposit);
+
+        let bounty = if with_bounty { fee_amount(deposit.storage_deposit.0, CLEANUP_BOUNTY_BPS) } else { 0 };
+        let sender_amount = deposit.storage_deposit.0 - bounty + deposit.revealed_deposit.0;
+        self.near_liabilities -= deposit.revealed_deposit.0;
+        if sender_amount > 0 {
+            Promise::new(deposit.sender.clone()).transfer(NearToken::from_yoctonear(sender_amount));
+        }
+        if with_bounty {
+            EscrowCleanup { escrow_id, executor: &executor, bounty: U128(bounty) }.emit();
+        }
+
+        // Los depósitos reclamados antes de V2 no tienen depósito de seguridad
+        let executor_amount = deposit.safety_deposit.0 + bounty;
+        self.near_liabilities -= deposit.safety_deposit.0;
+        if executor_amount > 0 {
+            Promise::new(executor).transfer(NearToken::from_yoctonear(executor_amount));
+        }
+    }
+
+    /// NEAR de la cuenta que no está bloqueado en escrows, no se debe a nadie y no
+    /// hace falta para el almacenamiento.
+    fn internal_near_surplus(&self) -> u128 {
+        let storage_stake = u128::from(env::storage_usage()) * env::storage_byte_cost().as_yoctonear();
+        env::account_balance()
+            .as_yoctonear()
+            .saturating_sub(storage_stake + self.internal_held(&Asset::Near) + self.near_liabilities)
+    }
+
+    /// Importe de `asset` que el contrato guarda para otros: lo bloqueado en
+    /// escrows activos, las comisiones sin retirar y los saldos de tokens.
+    fn internal_held(&self, asset: &Asset) -> u128 {
+        let balances = match asset {
+            Asset::Near => 0,
+            Asset::Ft { token_id } => self.ft_balances_total.get(token_id).unwrap_or(0),
+        };
+        self.locked_value.get(asset).unwrap_or(0) + self.fees_owed.get(asset).unwrap_or(0) + balances
+    }
+
+    /// Suma tokens al saldo de `account_id`. La primera vez el almacenamiento de
+    /// la entrada sale de su saldo de NEAR; la entrada se queda aunque llegue a 0.
+    fn internal_deposit_ft(&mut self, account_id: &AccountId, token_id: &AccountId, amount: u128) -> Result<(), &'static str> {
+        let key = (account_id.clone(), token_id.clone());
+        if !self.ft_balances.contains_key(&key) {
+            let initial_storage = env::storage_usage();
+            self.ft_balances.insert(&key, &0);
+            let storage_cost = storage_cost_since(initial_storage);
+
+            let near_balance = self.near_balances.get(account_id).unwrap_or(0);
+            let Some(remaining) = near_balance.checked_sub(storage_cost) else {
+                self.ft_balances.remove(&key);
+                return Err("Saldo de NEAR insuficiente para el almacenamiento");
+            };
+            self.internal_set_near_balance(account_id, remaining);
+            self.near_liabilities -= storage_cost;
+        }
+
+        self.internal_credit_ft(account_id, token_id, amount);
+        Ok(())
+    }
+
+    fn internal_credit_ft(&mut self, account_id: &AccountId, token_id: &AccountId, amount: u128) {
+        let key = (account_id.clone(), token_id.clone());
+        let balance = self.ft_balances.get(&key).unwrap_or(0);
+        self.ft_balances.insert(&key, &(balance + amount));
+        let total = self.ft_balances_total.get(token_id).unwrap_or(0);
+        self.ft_balances_total.insert(token_id, &(total + amount));
+    }
+
+    fn internal_debit_ft(&mut self, account_id: &AccountId, token_id: &AccountId, amount: u128) -> Result<(), &'static str> {
+        let key = (account_id.clone(), token_id.clone());
+        let balance = self.ft_balances.get(&key).unwrap_or(0);
+        let remaining = balance.checked_sub(amount).ok_or("Saldo de tokens insuficiente")?;
+        self.ft_balances.insert(&key, &remaining);
+        let total = self.ft_balances_total.get(token_id).unwrap_or(0);
+        self.ft_balances_total.insert(token_id, &(total - amount));
+        Ok(())
+    }
+
+    /// Comprueba todo lo que hace falta para rellenar la orden salvo el saldo
+    /// de NEAR del maker y devuelve el importe mínimo actual en destino.
+    fn internal_check_order(&self, order: &Order, signature: &Base64VecU8) -> Result<u128, &'static str> {
+        if order.verifying_contract != env::current_account_id() {
+            return Err("La orden es de otro contrato");
+        }
+        let public_key = self.order_keys.get(&order.maker).ok_or("El maker no tiene clave para firmar órdenes")?;
+        order.verify_signature(&signature.0, &public_key)?;
+        if order.is_expired() {
+            return Err("La orden ha caducado");
+        }
+        if order.epoch != self.epochs.get(&order.maker).unwrap_or(0) {
+            return Err("La orden es de otra época del maker");
+        }
+        if self.cancelled_orders.contains(&(order.maker.clone(), order.hash())) {
+            return Err("La orden está cancelada");
+        }
+        if self.is_nonce_used(order.maker.clone(), order.nonce) {
+            return Err("El nonce de la orden ya se usó");
+        }
+
+        let limits = self.supported_tokens.get(&order.token).ok_or("El token no está soportado")?;
+        limits.check(order.amount)?;
+        let balance = self.ft_balances.get(&(order.maker.clone(), order.token.clone())).unwrap_or(0);
+        if balance < order.amount.0 {
+            return Err("Saldo de tokens insuficiente");
+        }
+        order.current_dst_amount()
+    }
+
+    /// Marca el nonce de una orden como usado.
+    fn internal_use_nonce(&mut self, maker: &AccountId, nonce: u64) {
+        let (series, bit) = nonce_slot(nonce);
+        let key = (maker.clone(), series);
+        let used = self.nonces.get(&key).unwrap_or(0);
+        require!(used & bit == 0, "El nonce de la orden ya se usó");
+        self.nonces.insert(&key, &(used | bit));
+    }
+
+    /// Apunta las comisiones de un pago ya confirmado de `amount` (antes de comisiones).
+    fn internal_accrue_fees(&mut self, deposit: &DepositInfo, amount: u128) {
+        let (protocol_fee, integrator_fee) = deposit.fees(amount);
+        self.internal_credit_fees(None, &deposit.asset, protocol_fee);
+        if let Some(fee) = &deposit.integrator_fee {
+            self.internal_credit_fees(Some(&fee.recipient), &deposit.asset, integrator_fee);
+        }
+    }
+
+    /// Suma al libro de `integrator` (o del protocolo si es `None`) y al total.
+    fn internal_credit_fees(&mut self, integrator: Option<&AccountId>, asset: &Asset, amount: u128) {
+        if amount == 0 {
+            return;
+        }
+        match integrator {
+            None => fees::credit(&mut self.protocol_fees, asset, amount),
+            Some(account_id) => {
+                // Los escrows sólo se crean con integradores registrados, cuya
+                // entrada no se borra; el libro no debería faltar nunca
+                let mut ledger = self.integrator_fees.get(account_id).unwrap_or_else(|| {
+                    UnorderedMap::new(StorageKey::IntegratorFeesInner { account_hash: env::sha256(account_id.as_bytes()) })
+                });
+                fees::credit(&mut ledger, asset, amount);
+                self.integrator_fees.insert(account_id, &ledger);
+            }
+        }
+        fees::credit(&mut self.fees_owed, asset, amount);
+    }
+
+    /// Descuenta del libro de `integrator` (o del protocolo si es `None`) y del total.
+    fn internal_debit_fees(&mut self, integrator: Option<&AccountId>, asset: &Asset, amount: u128) -> Result<(), &'static str> {
+        match integrator {
+            None => fees::debit(&mut self.protocol_fees, asset, amount)?,
+            Some(account_id) => {
+                // La entrada está pagada por el integrador y se queda a cero
+                let mut ledger = self.integrator_fees.get(account_id).ok_or("No hay suficientes comisiones acumuladas")?;
+                fees::debit_registered(&mut ledger, asset, amount)?;
+            }
+        }
+        fees::debit(&mut self.fees_owed, asset, amount)
+    }
+
+    fn internal_integrator_registered(&self, account_id: &AccountId, asset: &Asset) -> bool {
+        self.integrator_fees.get(account_id).is_some_and(|ledger| ledger.get(asset).is_some())
+    }
+
+    /// Retira comisiones de un libro y se las envía a `receiver_id`.
+    fn internal_withdraw_fees(&mut self, integrator: Option<AccountId>, receiver_id: AccountId, asset: Asset, amount: U128) -> Promise {
+        self.internal_debit_fees(integrator.as_ref(), &asset, amount.0)
+            .unwrap_or_else(|err| env::panic_str(err));
+        FeesWithdrawn { receiver: &receiver_id, asset: &asset, amount }.emit();
+
+        asset.transfer(receiver_id, amount).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_FEE_WITHDRAWAL)
+                .resolve_fee_withdrawal(integrator, asset, amount),
+        )
+    }
+
+    /// Coste de una entrada de `revealed_hashlocks`. Se mide con una clave que no
+    /// es hexadecimal, así que no puede coincidir con un hashlock real.
+    fn internal_revealed_entry_cost(&mut self) -> u128 {
+        let probe = "-".repeat(64);
+        let initial_storage = env::storage_usage();
+        self.revealed_hashlocks.insert(&probe, &0);
+        let storage_cost = storage_cost_since(initial_storage);
+        self.revealed_hashlocks.remove(&probe);
+        storage_cost
+    }
+
+    /// Guarda la primera vez que se revela un hashlock. Se guarda sólo el hash
+    /// con el algoritmo del depósito, y su almacenamiento sale de lo que el
+    /// sender prepagó en `revealed_deposit`, que deja de deberse.
+    fn internal_record_hashlock(&mut self, deposit: &mut DepositInfo, hashlock: &String) {
+        if self.revealed_hashlocks.contains_key(hashlock) {
+            return;
+        }
+        let initial_storage = env::storage_usage();
+        self.revealed_hashlocks.insert(hashlock, &env::block_timestamp());
+        // Los depósitos de V1 no prepagaron nada y el almacenamiento corre a
+        // cargo del contrato
+        let spent = storage_cost_since(initial_storage).min(deposit.revealed_deposit.0);
+        deposit.revealed_deposit = U128(deposit.revealed_deposit.0 - spent);
+        self.near_liabilities -= spent;
+    }
+
+    fn assert_owner(&self) {
+        require!(env::predecessor_account_id() == self.owner_id, "Sólo el owner puede hacer esto");
+    }
+
+    fn internal_set_paused(&mut self, paused: bool) {
+        assert_one_yocto();
+        self.assert_owner();
+        require!(self.paused != paused, if paused { "El contrato ya está pausado" } else { "El contrato no está pausado" });
+
+        self.paused = paused;
+        PauseChanged { owner: &self.owner_id, paused }.emit();
+    }
+
+    fn internal_get_deposit(&self, escrow_id: &String) -> Option<DepositInfo> {
+        self.deposits.get(escrow_id).map(DepositInfo::from)
+    }
+
+    fn internal_save_deposit(&mut self, escrow_id: &String, deposit: &DepositInfo) {
+        self.deposits.insert(escrow_id, &VersionedDepositInfo::from(deposit.clone()));
+    }
+
+    fn internal_lock(&mut self, asset: &Asset, amount: u128) {
+        let locked = self.locked_value.get(asset).unwrap_or(0);
+        self.locked_value.insert(asset, &(locked + amount));
+    }
+
+    /// Descuenta del valor bloqueado lo que ya ha salido del contrato.
+    fn internal_unlock(&mut self, asset: &Asset, amount: u128) {
+        let locked = self.locked_value.get(asset).unwrap_or(0).saturating_sub(amount);
+        if locked == 0 {
+            self.locked_value.remove(asset);
+        } else {
+            self.locked_value.insert(asset, &locked);
+        }
+    }
+
+    fn internal_index_deposit(&mut self, escrow_id: &String, deposit: &DepositInfo) {
+        let sender_key = |account_hash| StorageKey::DepositsBySenderInner { account_hash };
+        let taker_key = |account_hash| StorageKey::DepositsByTakerInner { account_hash };
+        index_insert(&mut self.deposits_by_sender, &deposit.sender, escrow_id, sender_key);
+        index_insert(&mut self.deposits_by_taker, &deposit.taker, escrow_id, taker_key);
+        if let Asset::Ft { token_id } = &deposit.asset {
+            let token_key = |account_hash| StorageKey::DepositsByTokenInner { account_hash };
+            index_insert(&mut self.deposits_by_token, token_id, escrow_id, token_key);
+        }
+    }
+
+    /// Borra el depósito y sus entradas en los índices.
+    fn internal_remove_deposit(&mut self, escrow_id: &String, deposit: &DepositInfo) {
+        self.deposits.remove(escrow_id);
+        index_remove(&mut self.deposits_by_sender, &deposit.sender, escrow_id);
+        index_remove(&mut self.deposits_by_taker, &deposit.taker, escrow_id);
+        if let Asset::Ft { token_id } = &deposit.asset {
+            index_remove(&mut self.deposits_by_token, token_id, escrow_id);
+        }
+    }
+
+    fn views_of(&self, escrow_ids: Vec<String>) -> Vec<DepositView> {
+        escrow_ids
+            .into_iter()
+            .filter_map(|escrow_id| {
+                let deposit = self.internal_get_deposit(&escrow_id)?;
+                Some(DepositView { escrow_id, status: deposit.status(), deposit })
+            })
+            .collect()
+    }
+
+    fn internal_set_near_balance(&mut self, account_id: &AccountId, balance: u128) {
+        if balance == 0 {
+            self.near_balances.remove(account_id);
+        } else {
+            self.near_balances.insert(account_id, &balance);
+        }
+    }
+
+    /// Comprueba el secreto y la etapa, marca el depósito como reclamado y
+    /// devuelve el pago al recipient sin las comisiones.
+    fn internal_claim(&mut self, escrow_id: String, secret: &str) -> Result<Payout, &'static str> {
+        let preimage = decode_secret(secret)?;
+        let mut deposit = self.internal_get_deposit(&escrow_id).ok_or("No hay depósito para ese escrow_id")?;
+
+        // Sólo vale el hash calculado con el algoritmo elegido en el depósito
+        if hex::encode(deposit.hash_algorithm.hash(&preimage)) != deposit.hashlock {
+            return Err("El secreto no corresponde con el hashlock");
+        }
+        deposit.check_not_settled()?;
+        if deposit.parts.is_some() {
+            return Err("Este depósito se reclama por partes con claim_partial");
+        }
+        deposit.check_withdrawal_allowed()?;
+        let hashlock = deposit.hashlock.clone();
+        self.internal_record_hashlock(&mut deposit, &hashlock);
+
+        let payout = deposit.clone();
+        deposit.claimed = true;
+        self.internal_save_deposit(&escrow_id, &deposit);
+
+        // Las comisiones se quedan en el contrato y se apuntan cuando se confirma el pago
+        let (protocol_fee, integrator_fee) = payout.fees(payout.amount.0);
+        EscrowClaim {
+            escrow_id: &escrow_id,
+            secret: &hex::encode(&preimage),
+            recipient: &payout.recipient,
+            executor: &env::predecessor_account_id(),
+            amount: payout.amount,
+            index: None,
+            protocol_fee: U128(protocol_fee),
+            integrator_fee: U128(integrator_fee),
+        }
+        .emit();
+
+        Ok(Payout {
+            receiver_id: payout.recipient.clone(),
+            amount: U128(payout.amount.0 - protocol_fee - integrator_fee),
+            escrow_id,
+            deposit: payout,
+        })
+    }
+
+    /// Comprueba la etapa, marca el depósito como devuelto y devuelve el pago al
+    /// sender. Si hubo rellenos parciales sólo se devuelve lo que queda.
+    fn internal_refund(&mut self, escrow_id: String) -> Result<Payout, &'static str> {
+        let mut deposit = self.internal_get_deposit(&escrow_id).ok_or("No hay depósito para ese escrow_id")?;
+        deposit.check_cancellation_allowed()?;
+        deposit.check_not_settled()?;
+
+        let payout = deposit.clone();
+        deposit.refunded = true;
+        self.internal_save_deposit(&escrow_id, &deposit);
+
+        let amount = U128(deposit.amount.0 - deposit.filled.0);
+        EscrowExpire {
+            escrow_id: &escrow_id,
+            sender: &deposit.sender,
+            expired_at: deposit.timelocks.cancellation_start(deposit.timestamp).into(),
+        }
+        .emit();
+        EscrowRefund { escrow_id: &escrow_id, sender: &deposit.sender, executor: &env::predecessor_account_id(), amount }
+            .emit();
+
+        Ok(Payout { receiver_id: deposit.sender, amount, escrow_id, deposit: payout })
+    }
+
+    /// Envía el pago en el activo del depósito (NEAR o NEP-141) y lo resuelve
+    /// en `resolve_payout`.
+    fn internal_payout(&mut self, payout: Payout, executor: AccountId) -> Promise {
+        payout
+            .deposit
+            .asset
+            .transfer(payout.receiver_id, payout.amount)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_PAYOUT)
+                    .resolve_payout(payout.escrow_id, payout.deposit, executor),
+            )
+    }
+
+    /// Envía los pagos de un lote agrupados por activo y receptor. Cada grupo se
+    /// resuelve en `resolve_batch_payout`. Si el gas que queda no cubre todas
+    /// las transferencias y callbacks se rechaza el lote entero.
+    fn internal_batch_payout(&mut self, payouts: Vec<Payout>, executor: AccountId, with_bounty: bool) {
+        let groups = group_payouts(payouts);
+        let callback_gas =
+            |group: &PayoutGroup| Gas::from_gas(GAS_FOR_RESOLVE_PAYOUT.as_gas() * group.payouts.len() as u64);
+        let required: u64 = groups
+            .iter()
+            .map(|group| {
+                group.asset.transfer_gas().as_gas() + callback_gas(group).as_gas() + GAS_FOR_BATCH_GROUP_RECEIPTS.as_gas()
+            })
+            .sum::<u64>()
+            + GAS_RESERVED_FOR_BATCH.as_gas();
+        let remaining = env::prepaid_gas().as_gas().saturating_sub(env::used_gas().as_gas());
+        require!(required <= remaining, "No hay gas suficiente para los pagos del lote");
+
+        for group in groups {
+
+            let callback_gas = callback_gas(&group);
+            group.asset.transfer(group.receiver_id, group.amount).then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(callback_gas)
+                    .resolve_batch_payout(group.payouts, executor.clone(), with_bounty),
+            );
+        }
+    }
+
+    /// Si el pago salió bien se cuenta, se apuntan las comisiones y se borra la
+    /// entrada; si no, se quita la marca de reclamado o devuelto. `deposit` es el
+    /// depósito antes de marcarlo y sólo dice lo que se pagó: los cambios se
+    /// hacen sobre el depósito guardado, que un relleno fallido puede haber
+    /// tocado mientras tanto.
+    fn internal_resolve_payout(
+        &mut self,
+        escrow_id: String,
+        deposit: DepositInfo,
+        executor: &AccountId,
+        paid: bool,
+        with_bounty: bool,
+    ) {
+        let Some(mut stored) = self.internal_get_deposit(&escrow_id) else {
+            return;
+        };
+        let amount = deposit.amount.0 - deposit.filled.0;
+        if !paid {
+            let reverted = if stored.refunded { "escrow_refund" } else { "escrow_claim" };
+            stored.claimed = false;
+            stored.refunded = false;
+            self.internal_save_deposit(&escrow_id, &stored);
+            EscrowRestore { escrow_id: &escrow_id, reverted, amount: U128(amount) }.emit();
+            return;
+        }
+
+        self.internal_unlock(&deposit.asset, amount);
+        if !stored.refunded {
+            self.stats.total_claimed += 1;
+            self.internal_accrue_fees(&deposit, amount);
+        } else if stored.filled.0 < deposit.filled.0 {
+            // Un relleno falló después de calcular la devolución: lo que se
+            // deshizo sigue en el escrow y se devuelve con otro retrieve_tokens
+            stored.filled = U128(stored.filled.0 + amount);
+            stored.refunded = false;
+            self.internal_save_deposit(&escrow_id, &stored);
+            return;
+        } else {
+            self.stats.total_refunded += 1;
+        }
+        self.internal_remove_settled(&escrow_id, &stored, executor.clone(), with_bounty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::test_utils::VMContextBuilder;
+
+    use super::*;
+
+    const ONE_YOCTO: NearToken = NearToken::from_yoctonear(1);
+
+    const SECRET: &str = "0102030405060708091011121314151617181920212223242526272829303132";
+
+    fn hashlock(algorithm: HashAlgorithm, secret: &str) -> String {
+        hex::encode(algorithm.hash(&hex::decode(secret).unwrap()))
+    }
+
+    const TIMELOCKS: Timelocks = Timelocks {
+        withdrawal: 60,
+        public_withdrawal: 120,
+        cancellation: 180,
+        public_cancellation: 240,
+    };
+
+    const ORDER_HASH: &str = "abababababababababababababababababababababababababababababababab";
+
+    const EVM_MAKER: EvmAddress = EvmAddress([0x11; 20]);
+
+    fn escrow_msg(hash: &str, hash_algorithm: HashAlgorithm) -> EscrowMsgV1 {
+        EscrowMsgV1 {
+            hashlock: hash.to_string(),
+            hash_algorithm: Some(hash_algorithm),
+            taker: "bob.near".parse().unwrap(),
+            recipient: None,
+            timelocks: None,
+            order_hash: ORDER_HASH.to_string(),
+            src_chain_id: ChainId::NEAR,
+            dst_chain_id: ChainId::new(1).unwrap(),
+            evm_maker: EVM_MAKER,
+            dst_token: EvmAddress::ZERO,
+            dst_amount: U128(1_000),
+            safety_deposit: None,
+            parts: None,
+            integrator_fee: None,
+        }
+    }
+
+    fn msg_json(msg: &EscrowMsgV1) -> String {
+        near_sdk::serde_json::to_string(&EscrowMsg::V1(msg.clone())).unwrap()
+    }
+
+    /// Cantidad que `ft_on_transfer` devuelve como no usada
+    fn unused(result: PromiseOrValue<U128>) -> U128 {
+        match result {
+            PromiseOrValue::Value(amount) => amount,
+            PromiseOrValue::Promise(_) => panic!("ft_on_transfer no debería devolver una promesa"),
+        }
+    }
+
+    /// Argumentos JSON de la primera llamada a función del receipt
+    fn function_call_args(receipt: &near_sdk::mock::Receipt) -> near_sdk::serde_json::Value {
+        receipt
+            .actions
+            .iter()
+            .find_map(|action| match action {
+                near_sdk::mock::MockAction::FunctionCallWeight { args, .. } => {
+                    Some(near_sdk::serde_json::from_slice(args).unwrap())
+                }
+                _ => None,
+            })
+            .expect("El receipt no tiene llamadas a función")
+    }
+
+    /// Contexto con `predecessor` llamando `seconds` después de crear el depósito
+    fn at_stage(predecessor: &str, seconds: u64) {
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(predecessor.parse().unwrap())
+            .block_timestamp(seconds * 1_000_000_000)
+            .build());
+    }
+
+    fn staged_contract() -> (Contract, String) {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        at_stage("alice.near", 0);
+        contract.internal_save_deposit(&hash, &DepositInfo {
+            timelocks: TIMELOCKS,
+            ..deposit_info(alice, &hash, HashAlgorithm::Sha256)
+        });
+
+        (contract, hash)
+    }
+
+    fn deposit_info(sender: AccountId, hash: &str, hash_algorithm: HashAlgorithm) -> DepositInfo {
+        DepositInfo {
+            sender,
+            amount: U128::from(1_000_000_000_000_000_000_000_000),
+            claimed: false,
+            timestamp: env::block_timestamp(),
+            hashlock: hash.to_string(),
+            hash_algorithm,
+            asset: Asset::Near,
+            taker: "bob.near".parse().unwrap(),
+            timelocks: Timelocks::DEFAULT,
+            recipient: "bob.near".parse().unwrap(),
+            order_hash: ORDER_HASH.to_string(),
+            src_chain_id: ChainId::NEAR,
+            dst_chain_id: ChainId::new(1).unwrap(),
+            evm_maker: EVM_MAKER,
+            dst_token: EvmAddress::ZERO,
+            dst_amount: U128(1_000),
+            safety_deposit: U128(0),
+            safety_deposit_payer: None,
+            parts: None,
+            filled: U128(0),
+            refunded: false,
+            storage_deposit: U128(0),
+            revealed_deposit: U128(0),
+            protocol_fee_bps: 0,
+            integrator_fee: None,
+        }
+    }
+
+    /// Contrato que acepta token.near
+    fn new_contract() -> Contract {
+        let token = SupportedToken { token_id: "token.near".parse().unwrap(), limits: TokenLimits::default() };
+        Contract::init("owner.near".parse().unwrap(), None, vec![token])
+    }
+
+    /// escrow_id del único depósito del contrato
+    fn only_escrow_id(contract: &Contract) -> String {
+        assert_eq!(contract.deposits.len(), 1);
+        contract.deposits.keys_as_vector().get(0).unwrap()
+    }
+
+    /// Saldo de NEAR con el que `account_id` paga el almacenamiento y los depósitos de
+    /// seguridad. Deja el contexto de token.near
+    fn fund_near(contract: &mut Contract, account_id: &AccountId) {
+        testing_env!(VMContextBuilder::new().attached_deposit(NearToken::from_near(1)).build());
+        contract.deposit_near(Some(account_id.clone()));
+        from_token();
+    }
+
+    /// Contexto de una llamada de token.near, como en `ft_on_transfer`
+    fn from_token() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id("token.near".parse().unwrap()).build());
+    }
+
+    fn ft_deposit_info(sender: AccountId, hash: &str) -> DepositInfo {
+        DepositInfo {
+            asset: Asset::Ft { token_id: "token.near".parse().unwrap() },
+            ..deposit_info(sender, hash, HashAlgorithm::Sha256)
+        }
+    }
+
+    #[test]
+    fn init_contract() {
+        let contract = new_contract();
+
+        let config = contract.get_config();
+        assert_eq!(config.owner_id, "owner.near".parse::<AccountId>().unwrap());
+        assert_eq!(config.default_timelocks, Timelocks::DEFAULT);
+        assert_eq!(config.supported_tokens[0].token_id, "token.near".parse::<AccountId>().unwrap());
+        assert_eq!(contract.get_stats().stats, Stats::default());
+    }
+
+      #[test]
+    fn test_on_transfer() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        fund_near(&mut contract, &alice);
+        let token: AccountId = "token.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(token.clone()).build());
+
+        contract.ft_on_transfer(alice.clone(), U128(23), msg_json(&escrow_msg(&hash, HashAlgorithm::Sha256)));
+
+        let value = contract.internal_get_deposit(&only_escrow_id(&contract)).unwrap();
+
+        assert_eq!(value.sender, alice);
+        assert_eq!(value.amount, U128(23));
+        assert!(!value.claimed);
+        assert_eq!(value.timestamp, env::block_timestamp());
+        assert_eq!(value.hashlock, hash);
+        assert_eq!(value.hash_algorithm, HashAlgorithm::Sha256);
+        assert_eq!(value.asset, Asset::Ft { token_id: token });
+        assert_eq!(value.taker, "bob.near".parse::<AccountId>().unwrap());
+        assert_eq!(value.timelocks, Timelocks::DEFAULT);
+    }
+
+    #[test]
+    fn test_on_transfer_keccak256() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        fund_near(&mut contract, &alice);
+        let hash = hashlock(HashAlgorithm::Keccak256, SECRET);
+
+        let msg = escrow_msg(&hash.to_uppercase(), HashAlgorithm::Keccak256);
+        contract.ft_on_transfer(alice.clone(), U128(23), msg_json(&msg));
+
+        let value = contract.internal_get_deposit(&only_escrow_id(&contract)).unwrap();
+
+        assert_eq!(value.hashlock, hash);
+        assert_eq!(value.hash_algorithm, HashAlgorithm::Keccak256);
+    }
+
+    #[test]
+    fn test_on_transfer_json_format() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Keccak256, SECRET);
+        let msg = format!(
+            r#"{{"version":"1","hashlock":"{}","hash_algorithm":"keccak256","taker":"bob.near","recipient":"carol.near","order_hash":"{}","src_chain_id":397,"dst_chain_id":42161,"evm_maker":"0x{}","dst_token":"0xAF88D065E77C8CC2239327C5EDB3A432268E5831","dst_amount":"990000","safety_deposit":"1000"}}"#,
+            hash, ORDER_HASH, "11".repeat(20)
+        );
+
+        fund_near(&mut contract, &alice);
+
+        assert_eq!(unused(contract.ft_on_transfer(alice, U128(23), msg)), U128(0));
+
+        let value = contract.internal_get_deposit(&only_escrow_id(&contract)).unwrap();
+        assert_eq!(value.hash_algorithm, HashAlgorithm::Keccak256);
+        assert_eq!(value.recipient, "carol.near".parse::<AccountId>().unwrap());
+        assert_eq!(value.order_hash, ORDER_HASH);
+        assert_eq!(value.src_chain_id, ChainId::NEAR);
+        assert_eq!(value.dst_chain_id.get(), 42161);
+        assert_eq!(value.evm_maker, EVM_MAKER);
+        assert_eq!(String::from(value.dst_token), "0xaf88d065e77c8cc2239327c5edb3a432268e5831");
+        assert_eq!(value.dst_amount, U128(990_000));
+        assert_eq!(value.safety_deposit, U128(1000));
+        assert_eq!(value.timelocks, Timelocks::DEFAULT);
+    }
+
+    #[test]
+    fn test_on_transfer_malformed_msg_refunds() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        from_token();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        assert_eq!(unused(contract.ft_on_transfer(alice.clone(), U128(23), hash)), U128(23));
+        assert_eq!(unused(contract.ft_on_transfer(alice, U128(23), "{}".to_string())), U128(23));
+        assert_eq!(contract.deposits.len(), 0);
+    }
+
+    #[test]
+    fn test_on_transfer_unsupported_token_refunds() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        fund_near(&mut contract, &alice);
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id("other.near".parse().unwrap()).build());
+        let msg = msg_json(&escrow_msg(&hash, HashAlgorithm::Sha256));
+        assert_eq!(unused(contract.ft_on_transfer(alice, U128(23), msg)), U128(23));
+        assert_eq!(contract.deposits.len(), 0);
+    }
+
+    #[test]
+    fn test_on_transfer_unknown_version_refunds() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        from_token();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let msg = msg_json(&escrow_msg(&hash, HashAlgorithm::Sha256)).replace(r#""version":"1""#, r#""version":"2""#);
+
+        assert_eq!(unused(contract.ft_on_transfer(alice, U128(23), msg)), U128(23));
+        assert_eq!(contract.deposits.len(), 0);
+    }
+
+    #[test]
+    fn test_on_transfer_invalid_hashlock_refunds() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        from_token();
+        let msg = escrow_msg("abcd", HashAlgorithm::Sha256);
+
+        assert_eq!(unused(contract.ft_on_transfer(alice, U128(23), msg_json(&msg))), U128(23));
+        assert_eq!(contract.deposits.len(), 0);
+    }
+
+    #[test]
+    fn test_on_transfer_invalid_order_hash_refunds() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        from_token();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let msg = EscrowMsgV1 {
+            order_hash: "zz".to_string(),
+            ..escrow_msg(&hash, HashAlgorithm::Sha256)
+        };
+
+        assert_eq!(unused(contract.ft_on_transfer(alice, U128(23), msg_json(&msg))), U128(23));
+        assert_eq!(contract.deposits.len(), 0);
+    }
+
+    #[test]
+    fn test_on_transfer_duplicate_escrow_refunds() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        fund_near(&mut contract, &alice);
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let msg = msg_json(&escrow_msg(&hash, HashAlgorithm::Sha256));
+
+        assert_eq!(unused(contract.ft_on_transfer(alice.clone(), U128(23), msg.clone())), U128(0));
+        assert_eq!(unused(contract.ft_on_transfer(alice.clone(), U128(23), msg.clone())), U128(23));
+        assert_eq!(contract.internal_get_deposit(&only_escrow_id(&contract)).unwrap().amount, U128(23));
+
+        // Con otro importe los Immutables cambian y es otro escrow
+        assert_eq!(unused(contract.ft_on_transfer(alice, U128(50), msg)), U128(0));
+        assert_eq!(contract.deposits.len(), 2);
+    }
+
+    #[test]
+    fn test_on_transfer_with_timelocks() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        fund_near(&mut contract, &alice);
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let msg = EscrowMsgV1 {
+            timelocks: Some(TIMELOCKS),
+            ..escrow_msg(&hash, HashAlgorithm::Sha256)
+        };
+
+        contract.ft_on_transfer(alice, U128(23), msg_json(&msg));
+
+        assert_eq!(contract.internal_get_deposit(&only_escrow_id(&contract)).unwrap().timelocks, TIMELOCKS);
+    }
+
+    #[test]
+    fn test_on_transfer_invalid_timelocks_refunds() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        from_token();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let msg = EscrowMsgV1 {
+            timelocks: Some(Timelocks { cancellation: 100, ..TIMELOCKS }),
+            ..escrow_msg(&hash, HashAlgorithm::Sha256)
+        };
+
+        assert_eq!(unused(contract.ft_on_transfer(alice, U128(23), msg_json(&msg))), U128(23));
+        assert_eq!(contract.deposits.len(), 0);
+    }
+
+    //este test no va aquí, hay que hacer un test de integración
+     #[test]
+    fn recive_near() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        let mut builder = VMContextBuilder::new();
+        builder
+            .attached_deposit(NearToken::from_near(1))
+            .predecessor_account_id(alice.clone());
+
+        testing_env!(builder.build());
+
+        contract.recive_near(EscrowMsg::V1(escrow_msg(&hash, HashAlgorithm::Sha256)));
+  
+        let value = contract.internal_get_deposit(&only_escrow_id(&contract)).unwrap();
+
+        //println!("{:?}", value);
+        let attached_deposit =
+            NearToken::from_near(1).as_yoctonear() - value.storage_deposit.0 - contract.internal_revealed_entry_cost();
+
+        assert!(value.storage_deposit.0 > 0);
+        assert_eq!(value.sender, alice);
+        assert_eq!(value.amount, attached_deposit.into());
+        assert!(!value.claimed);
+        assert_eq!(value.timestamp, env::block_timestamp());
+        assert_eq!(value.asset, Asset::Near);
+    }
+
+    #[test]
+    #[should_panic(expected = "El hashlock debe ser de 32 bytes en hexadecimal")]
+    fn recive_near_invalid_hashlock() {
+        let mut contract = new_contract();
+
+        testing_env!(VMContextBuilder::new().attached_deposit(NearToken::from_near(1)).build());
+
+        contract.recive_near(EscrowMsg::V1(escrow_msg("abcd", HashAlgorithm::Sha256)));
+    }
+
+    #[test]
+    fn claim_tokens(){
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        let mut builder = VMContextBuilder::new();
+        builder
+            .attached_deposit(NearToken::from_near(1))
+            .predecessor_account_id(alice.clone());
+
+        testing_env!(builder.build());
+
+        let deposit_info = deposit_info(alice.clone(), &hash, HashAlgorithm::Sha256);
+
+        contract.internal_save_deposit(&hash, &deposit_info);
+        contract.claim_tokens(hash.clone(), SECRET.to_string());
+
+        let updated_deposit: DepositInfo = contract.internal_get_deposit(&hash).unwrap();
+
+        assert!(updated_deposit.claimed, "Deposit has not been claimed yet");
+    }
+
+    #[test]
+    fn claim_tokens_keccak256() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Keccak256, SECRET);
+
+        contract.internal_save_deposit(&hash, &deposit_info(alice, &hash, HashAlgorithm::Keccak256));
+        contract.claim_tokens(hash.clone(), SECRET.to_string());
+
+        assert!(contract.internal_get_deposit(&hash).unwrap().claimed);
+    }
+
+    #[test]
+    #[should_panic(expected = "El secreto no corresponde con el hashlock")]
+    fn claim_tokens_wrong_preimage() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        contract.internal_save_deposit(&hash, &deposit_info(alice, &hash, HashAlgorithm::Sha256));
+        contract.claim_tokens(hash.clone(), "ff".repeat(32));
+    }
+
+    #[test]
+    #[should_panic(expected = "El secreto no corresponde con el hashlock")]
+    fn claim_tokens_with_hashlock_as_secret() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        // Conocer la clave del depósito no basta para reclamarlo
+        contract.internal_save_deposit(&hash, &deposit_info(alice, &hash, HashAlgorithm::Sha256));
+        contract.claim_tokens(hash.clone(), hash);
+    }
+
+    #[test]
+    #[should_panic(expected = "El secreto no corresponde con el hashlock")]
+    fn claim_tokens_wrong_algorithm() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Keccak256, SECRET);
+
+        // El hashlock es keccak256 pero el depósito se creó como sha256
+        contract.internal_save_deposit(&hash, &deposit_info(alice, &hash, HashAlgorithm::Sha256));
+        contract.claim_tokens(hash.clone(), SECRET.to_string());
+    }
+
+    #[test]
+    fn retrieve_tokens() {
+         let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        let deposit_info = deposit_info(alice.clone(), &hash, HashAlgorithm::Sha256);
+
+        let mut builder = VMContextBuilder::new();
+        builder
+            .attached_deposit(NearToken::from_near(1))
+            .predecessor_account_id(alice.clone())
+            .block_timestamp(deposit_info.timestamp + 25 * 3600 * 1_000_000_000);
+
+        testing_env!(builder.build());
+
+        contract.internal_save_deposit(&hash, &deposit_info);
+
+        contract.retrieve_tokens(hash.clone());
+
+        assert!(contract.internal_get_deposit(&hash).unwrap().refunded, "Deposit was not marked as refunded after retrieving the tokens");
+    }
+
+    #[test]
+    fn claim_ft_tokens_calls_ft_transfer() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        contract.internal_save_deposit(&hash, &ft_deposit_info(alice, &hash));
+        contract.claim_tokens(hash.clone(), SECRET.to_string());
+
+        assert!(contract.internal_get_deposit(&hash).unwrap().claimed);
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, "token.near".parse::<AccountId>().unwrap());
+    }
+
+    #[test]
+    fn resolve_payout_failed_claim_marks_unclaimed() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let deposit = ft_deposit_info(alice, &hash);
+
+        contract.internal_save_deposit(&hash, &DepositInfo { claimed: true, ..deposit.clone() });
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(env::current_account_id())
+            .build());
+
+        assert!(!contract.resolve_payout(hash.clone(), deposit, "carol.near".parse().unwrap(), Err(PromiseError::Failed)));
+        assert!(!contract.internal_get_deposit(&hash).unwrap().claimed);
+
+        // El escrow_claim que se emitió al reclamar queda anulado
+        let events = events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["event"], "escrow_restore");
+        assert_eq!(events[0]["data"][0]["escrow_id"], hash);
+        assert_eq!(events[0]["data"][0]["reverted"], "escrow_claim");
+        assert_eq!(events[0]["data"][0]["amount"], "1000000000000000000000000");
+    }
+
+    #[test]
+    fn resolve_payout_failed_refund_restores_deposit() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let deposit = ft_deposit_info(alice, &hash);
+        contract.internal_save_deposit(&hash, &DepositInfo { refunded: true, ..deposit.clone() });
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(env::current_account_id())
+            .build());
+
+        assert!(!contract.resolve_payout(hash.clone(), deposit, "carol.near".parse().unwrap(), Err(PromiseError::Failed)));
+        let restored = contract.internal_get_deposit(&hash).unwrap();
+        assert_eq!(restored.amount, U128::from(1_000_000_000_000_000_000_000_000));
+        assert!(!restored.refunded);
+        assert_eq!(events()[0]["event"], "escrow_restore");
+        assert_eq!(events()[0]["data"][0]["reverted"], "escrow_refund");
+    }
+
+    #[test]
+    fn resolve_payout_success_removes_deposit() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let deposit = DepositInfo {
+            storage_deposit: U128(1000),
+            ..ft_deposit_info(alice.clone(), &hash)
+        };
+
+        contract.internal_save_deposit(&hash, &DepositInfo { claimed: true, ..deposit.clone() });
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(env::current_account_id())
+            .build());
+
+        assert!(contract.resolve_payout(hash.clone(), deposit, "carol.near".parse().unwrap(), Ok(())));
+        assert!(contract.internal_get_deposit(&hash).is_none());
+
+        // Se devuelve al sender el almacenamiento que ocupaba
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, alice);
+        assert!(matches!(
+            receipts[0].actions[0],
+            near_sdk::mock::MockAction::Transfer { deposit, .. } if deposit == NearToken::from_yoctonear(1000)
+        ));
+    }
+
+    #[test]
+    fn claim_near_deposit_transfers_near() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let bob: AccountId = "bob.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(alice.clone()).build());
+
+        contract.internal_save_deposit(&hash, &deposit_info(alice, &hash, HashAlgorithm::Sha256));
+        contract.claim_tokens(hash.clone(), SECRET.to_string());
+
+        assert!(contract.internal_get_deposit(&hash).unwrap().claimed);
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, bob);
+    }
+
+    #[test]
+    fn retrieve_ft_deposit_calls_ft_transfer() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let deposit = ft_deposit_info(alice, &hash);
+
+        testing_env!(VMContextBuilder::new()
+            .block_timestamp(deposit.timestamp + 25 * 3600 * 1_000_000_000)
+            .build());
+
+        contract.internal_save_deposit(&hash, &deposit);
+        contract.retrieve_tokens(hash.clone());
+
+        assert!(contract.internal_get_deposit(&hash).unwrap().refunded);
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, "token.near".parse::<AccountId>().unwrap());
+    }
+
+    #[test]
+    fn timelock_stages() {
+        assert_eq!(TIMELOCKS.stage(0, 59 * 1_000_000_000), Stage::Locked);
+        assert_eq!(TIMELOCKS.stage(0, 60 * 1_000_000_000), Stage::Withdrawal);
+        assert_eq!(TIMELOCKS.stage(0, 120 * 1_000_000_000), Stage::PublicWithdrawal);
+        assert_eq!(TIMELOCKS.stage(0, 180 * 1_000_000_000), Stage::Cancellation);
+        assert_eq!(TIMELOCKS.stage(0, 240 * 1_000_000_000), Stage::PublicCancellation);
+    }
+
+    #[test]
+    #[should_panic(expected = "El depósito no está en periodo de retirada")]
+    fn claim_tokens_locked() {
+        let (mut contract, hash) = staged_contract();
+
+        at_stage("bob.near", 30);
+        contract.claim_tokens(hash, SECRET.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Sólo el taker puede reclamar en la ventana exclusiva")]
+    fn claim_tokens_exclusive_withdrawal_not_taker() {
+        let (mut contract, hash) = staged_contract();
+
+        at_stage("carol.near", 90);
+        contract.claim_tokens(hash, SECRET.to_string());
+    }
+
+    #[test]
+    fn claim_tokens_exclusive_withdrawal_taker() {
+        let (mut contract, hash) = staged_contract();
+
+        at_stage("bob.near", 90);
+        contract.claim_tokens(hash.clone(), SECRET.to_string());
+
+        assert!(contract.internal_get_deposit(&hash).unwrap().claimed);
+    }
+
+    #[test]
+    fn claim_tokens_public_withdrawal() {
+        let (mut contract, hash) = staged_contract();
+
+        at_stage("carol.near", 150);
+        contract.claim_tokens(hash.clone(), SECRET.to_string());
+
+        assert!(contract.internal_get_deposit(&hash).unwrap().claimed);
+    }
+
+    #[test]
+    #[should_panic(expected = "El depósito no está en periodo de retirada")]
+    fn claim_tokens_after_cancellation() {
+        let (mut contract, hash) = staged_contract();
+
+        at_stage("bob.near", 200);
+        contract.claim_tokens(hash, SECRET.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "El tiempo de espera aún no ha pasado")]
+    fn retrieve_tokens_during_withdrawal() {
+        let (mut contract, hash) = staged_contract();
+
+        at_stage("bob.near", 150);
+        contract.retrieve_tokens(hash);
+    }
+
+    #[test]
+    #[should_panic(expected = "Sólo el taker puede cancelar en la ventana exclusiva")]
+    fn retrieve_tokens_exclusive_cancellation_not_taker() {
+        let (mut contract, hash) = staged_contract();
+
+        at_stage("alice.near", 200);
+        contract.retrieve_tokens(hash);
+    }
+
+    #[test]
+    fn retrieve_tokens_exclusive_cancellation_taker() {
+        let (mut contract, hash) = staged_contract();
+
+        at_stage("bob.near", 200);
+        contract.retrieve_tokens(hash.clone());
+
+        assert!(contract.internal_get_deposit(&hash).unwrap().refunded);
+    }
+
+    #[test]
+    fn retrieve_tokens_public_cancellation() {
+        let (mut contract, hash) = staged_contract();
+
+        at_stage("carol.near", 240);
+        contract.retrieve_tokens(hash.clone());
+
+        assert!(contract.internal_get_deposit(&hash).unwrap().refunded);
+    }
+
+    #[test]
+    fn claim_tokens_public_withdrawal_pays_recipient() {
+        let (mut contract, hash) = staged_contract();
+        let mut deposit = contract.internal_get_deposit(&hash).unwrap();
+        deposit.asset = Asset::Ft { token_id: "token.near".parse().unwrap() };
+        deposit.recipient = "dave.near".parse().unwrap();
+        contract.internal_save_deposit(&hash, &deposit);
+
+        // Quien conoce el secreto en la ventana pública no se queda con los fondos
+        at_stage("carol.near", 150);
+        contract.claim_tokens(hash.clone(), SECRET.to_string());
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(function_call_args(&receipts[0])["receiver_id"], "dave.near");
+    }
+
+    #[test]
+    fn claim_tokens_recipient_defaults_to_taker() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        fund_near(&mut contract, &alice);
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        contract.ft_on_transfer(alice, U128(23), msg_json(&escrow_msg(&hash, HashAlgorithm::Sha256)));
+
+        let value = contract.internal_get_deposit(&only_escrow_id(&contract)).unwrap();
+        assert_eq!(value.recipient, value.taker);
+    }
+
+    #[test]
+    fn recive_near_with_safety_deposit() {
+        let mut contract = new_contract();
+
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let msg = EscrowMsgV1 {
+            safety_deposit: Some(U128(NearToken::from_millinear(100).as_yoctonear())),
+            ..escrow_msg(&hash, HashAlgorithm::Sha256)
+        };
+
+        testing_env!(VMContextBuilder::new().attached_deposit(NearToken::from_near(1)).build());
+
+        contract.recive_near(EscrowMsg::V1(msg));
+
+        let value = contract.internal_get_deposit(&only_escrow_id(&contract)).unwrap();
+        assert_eq!(value.safety_deposit, U128(NearToken::from_millinear(100).as_yoctonear()));
+        assert_eq!(
+            value.amount,
+            U128(NearToken::from_millinear(900).as_yoctonear() - value.storage_deposit.0 - contract.internal_revealed_entry_cost())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "to cover for the storage cost and the safety deposit")]
+    fn recive_near_without_safety_deposit_attached() {
+        let mut contract = new_contract();
+
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let msg = EscrowMsgV1 {
+            safety_deposit: Some(U128(NearToken::from_near(1).as_yoctonear())),
+            ..escrow_msg(&hash, HashAlgorithm::Sha256)
+        };
+
+        testing_env!(VMContextBuilder::new().attached_deposit(NearToken::from_near(1)).build());
+
+        contract.recive_near(EscrowMsg::V1(msg));
+    }
+
+    #[test]
+    fn test_on_transfer_safety_deposit_from_near_balance() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let msg = EscrowMsgV1 {
+            safety_deposit: Some(U128(400)),
+            ..escrow_msg(&hash, HashAlgorithm::Sha256)
+        };
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(alice.clone())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        let balance = contract.deposit_near(None);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id("token.near".parse().unwrap()).build());
+        assert_eq!(unused(contract.ft_on_transfer(alice.clone(), U128(23), msg_json(&msg))), U128(0));
+
+        // Se cobra el depósito de seguridad, el almacenamiento del escrow y el
+        // del hash que se guardará al revelarlo
+        let value = contract.internal_get_deposit(&only_escrow_id(&contract)).unwrap();
+        assert_eq!(value.safety_deposit, U128(400));
+        assert!(value.storage_deposit.0 > 0);
+        let revealed_cost = contract.internal_revealed_entry_cost();
+        assert_eq!(contract.get_near_balance(alice), U128(balance.0 - 400 - value.storage_deposit.0 - revealed_cost));
+    }
+
+    #[test]
+    fn test_on_transfer_insufficient_near_balance_refunds() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        from_token();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let msg = EscrowMsgV1 {
+            safety_deposit: Some(U128(400)),
+            ..escrow_msg(&hash, HashAlgorithm::Sha256)
+        };
+
+        assert_eq!(unused(contract.ft_on_transfer(alice.clone(), U128(23), msg_json(&msg))), U128(23));
+        assert_eq!(contract.deposits.len(), 0);
+    }
+
+    #[test]
+    fn withdraw_near_balance() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(alice.clone())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        let balance = contract.deposit_near(None);
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(alice.clone())
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.withdraw_near(balance);
+
+        assert_eq!(contract.get_near_balance(alice.clone()), U128(0));
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, alice);
+    }
+
+    #[test]
+    #[should_panic(expected = "Saldo de NEAR insuficiente")]
+    fn withdraw_near_more_than_balance() {
+        let mut contract = new_contract();
+
+        testing_env!(VMContextBuilder::new().attached_deposit(ONE_YOCTO).build());
+        contract.withdraw_near(U128(1));
+    }
+
+    #[test]
+    fn resolve_payout_pays_safety_deposit_to_executor() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let carol: AccountId = "carol.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let deposit = DepositInfo {
+            safety_deposit: U128(500),
+            ..ft_deposit_info(alice, &hash)
+        };
+        contract.near_liabilities = 500;
+        let claimed = DepositInfo { claimed: true, ..deposit.clone() };
+        contract.internal_save_deposit(&hash, &claimed);
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(env::current_account_id())
+            .build());
+
+        assert!(contract.resolve_payout(hash.clone(), deposit.clone(), carol.clone(), Ok(())));
+        assert_eq!(contract.near_liabilities, 0);
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, carol);
+        assert!(matches!(
+            receipts[0].actions[0],
+            near_sdk::mock::MockAction::Transfer { deposit, .. } if deposit == NearToken::from_yoctonear(500)
+        ));
+
+        // Si el pago falla el depósito de seguridad sigue bloqueado
+        contract.internal_save_deposit(&hash, &claimed);
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(env::current_account_id())
+            .build());
+        assert!(!contract.resolve_payout(hash, deposit, carol, Err(PromiseError::Failed)));
+        assert!(near_sdk::test_utils::get_created_receipts().is_empty());
+    }
+
+    fn part_secret(index: u64) -> String {
+        hex::encode([index as u8 + 1; 32])
+    }
+
+    /// Árbol Merkle de los secretos `0..=parts`; devuelve la raíz y la prueba de cada hoja
+    fn merkle_tree(parts: u16) -> (String, Vec<Vec<String>>) {
+        let mut level: Vec<Vec<u8>> = (0..=u64::from(parts))
+            .map(|index| merkle::leaf(index, &env::sha256(&hex::decode(part_secret(index)).unwrap())))
+            .collect();
+        // Posición de cada hoja en el nivel actual
+        let mut positions: Vec<usize> = (0..level.len()).collect();
+        let mut proofs = vec![vec![]; level.len()];
+
+        while level.len() > 1 {
+            for (leaf, position) in positions.iter_mut().enumerate() {
+                let sibling = *position ^ 1;
+                if sibling < level.len() {
+                    proofs[leaf].push(hex::encode(&level[sibling]));
+                }
+                *position /= 2;
+            }
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [a, b] => merkle::hash_pair(a, b),
+                    [a] => a.clone(),
+                    _ => unreachable!(),
+                })
+                .collect();
+        }
+
+        (hex::encode(&level[0]), proofs)
+    }
+
+    /// Depósito FT de 100 unidades en 4 partes
+    fn partial_contract() -> (Contract, String, Vec<Vec<String>>) {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        fund_near(&mut contract, &alice);
+        let (root, proofs) = merkle_tree(4);
+        let msg = EscrowMsgV1 {
+            parts: Some(4),
+            ..escrow_msg(&root, HashAlgorithm::Sha256)
+        };
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id("token.near".parse().unwrap()).build());
+        assert_eq!(unused(contract.ft_on_transfer(alice, U128(100), msg_json(&msg))), U128(0));
+
+        at_stage("bob.near", 0);
+        let escrow_id = only_escrow_id(&contract);
+        (contract, escrow_id, proofs)
+    }
+
+    #[test]
+    fn claim_partial_releases_proportional_amounts() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+
+        contract.claim_partial(escrow_id.clone(), part_secret(0), 0, proofs[0].clone(), U128(25));
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(function_call_args(&receipts[0])["amount"], "25");
+        assert_eq!(contract.internal_get_deposit(&escrow_id).unwrap().filled, U128(25));
+
+        at_stage("bob.near", 0);
+        contract.claim_partial(escrow_id.clone(), part_secret(2), 2, proofs[2].clone(), U128(70));
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(function_call_args(&receipts[0])["amount"], "45");
+
+        at_stage("bob.near", 0);
+        contract.claim_partial(escrow_id.clone(), part_secret(4), 4, proofs[4].clone(), U128(100));
+
+        let deposit = contract.internal_get_deposit(&escrow_id).unwrap();
+        assert_eq!(deposit.filled, U128(100));
+        assert!(deposit.claimed);
+    }
+
+    #[test]
+    #[should_panic(expected = "Relleno no válido")]
+    fn claim_partial_rejects_overlapping_fill() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+
+        contract.claim_partial(escrow_id.clone(), part_secret(1), 1, proofs[1].clone(), U128(50));
+        contract.claim_partial(escrow_id, part_secret(1), 1, proofs[1].clone(), U128(40));
+    }
+
+    #[test]
+    #[should_panic(expected = "Relleno no válido")]
+    fn claim_partial_rejects_same_part_twice() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+
+        contract.claim_partial(escrow_id.clone(), part_secret(1), 1, proofs[1].clone(), U128(30));
+        contract.claim_partial(escrow_id, part_secret(1), 1, proofs[1].clone(), U128(40));
+    }
+
+    #[test]
+    #[should_panic(expected = "El índice del secreto no corresponde con el relleno")]
+    fn claim_partial_rejects_out_of_order_index() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+
+        contract.claim_partial(escrow_id.clone(), part_secret(2), 2, proofs[2].clone(), U128(60));
+        contract.claim_partial(escrow_id, part_secret(1), 1, proofs[1].clone(), U128(80));
+    }
+
+    #[test]
+    #[should_panic(expected = "La prueba Merkle no es válida")]
+    fn claim_partial_rejects_wrong_secret() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+
+        contract.claim_partial(escrow_id, part_secret(1), 0, proofs[0].clone(), U128(10));
+    }
+
+    #[test]
+    #[should_panic(expected = "Este depósito se reclama por partes con claim_partial")]
+    fn claim_tokens_rejects_partial_deposit() {
+        let (mut contract, escrow_id, _) = partial_contract();
+        let mut deposit = contract.internal_get_deposit(&escrow_id).unwrap();
+
+        // Aunque el hashlock coincidiera con el hash de un secreto
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        deposit.hashlock = hash.clone();
+        contract.internal_save_deposit(&hash, &deposit);
+
+        contract.claim_tokens(hash.clone(), SECRET.to_string());
+    }
+
+    #[test]
+    fn retrieve_tokens_after_partial_fill_refunds_remainder() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+
+        contract.claim_partial(escrow_id.clone(), part_secret(0), 0, proofs[0].clone(), U128(25));
+
+        at_stage("carol.near", 60 * 60 * 24);
+        contract.retrieve_tokens(escrow_id.clone());
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(function_call_args(&receipts[0])["receiver_id"], "alice.near");
+        assert_eq!(function_call_args(&receipts[0])["amount"], "75");
+    }
+
+    #[test]
+    fn resolve_fill_failure_rolls_back_filled() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+
+        contract.claim_partial(escrow_id.clone(), part_secret(4), 4, proofs[4].clone(), U128(100));
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(env::current_account_id())
+            .build());
+        let deposit = contract.internal_get_deposit(&escrow_id).unwrap();
+        assert!(!contract.resolve_fill(escrow_id.clone(), deposit, U128(100), Some("bob.near".parse().unwrap()), Err(PromiseError::Failed)));
+
+        let deposit = contract.internal_get_deposit(&escrow_id).unwrap();
+        assert_eq!(deposit.filled, U128(0));
+        assert!(!deposit.claimed);
+        assert_eq!(events()[0]["event"], "escrow_restore");
+        assert_eq!(events()[0]["data"][0]["reverted"], "escrow_claim");
+        assert_eq!(events()[0]["data"][0]["amount"], "100");
+    }
+
+    #[test]
+    fn resolve_fill_completed_refunds_storage() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+        let storage_deposit = contract.internal_get_deposit(&escrow_id).unwrap().storage_deposit;
+
+        contract.claim_partial(escrow_id.clone(), part_secret(4), 4, proofs[4].clone(), U128(100));
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(env::current_account_id())
+            .build());
+        let deposit = contract.internal_get_deposit(&escrow_id).unwrap();
+        // Sólo se guardaron el hash de la parte y la raíz
+        let unspent = deposit.revealed_deposit.0;
+        assert_eq!(unspent, contract.internal_revealed_entry_cost() * 4);
+        assert!(contract.resolve_fill(escrow_id.clone(), deposit, U128(100), Some("bob.near".parse().unwrap()), Ok(())));
+
+        assert!(contract.internal_get_deposit(&escrow_id).is_none());
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, "alice.near".parse::<AccountId>().unwrap());
+        assert!(matches!(
+            receipts[0].actions[0],
+            near_sdk::mock::MockAction::Transfer { deposit, .. } if deposit.as_yoctonear() == storage_deposit.0 + unspent
+        ));
+    }
+
+    /// Contexto de un callback del propio contrato
+    fn as_callback() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(env::current_account_id()).build());
+    }
+
+    #[test]
+    fn resolve_fill_failure_keeps_later_fills() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+
+        contract.claim_partial(escrow_id.clone(), part_secret(0), 0, proofs[0].clone(), U128(25));
+        let first = contract.internal_get_deposit(&escrow_id).unwrap();
+        at_stage("bob.near", 0);
+        contract.claim_partial(escrow_id.clone(), part_secret(2), 2, proofs[2].clone(), U128(70));
+
+        // El primer relleno falla después de que entrara el segundo
+        as_callback();
+        assert!(!contract.resolve_fill(escrow_id.clone(), first, U128(25), None, Err(PromiseError::Failed)));
+
+        assert_eq!(contract.internal_get_deposit(&escrow_id).unwrap().filled, U128(45));
+    }
+
+    #[test]
+    fn failed_fill_keeps_a_completed_escrow_open() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+
+        contract.claim_partial(escrow_id.clone(), part_secret(0), 0, proofs[0].clone(), U128(25));
+        let first = contract.internal_get_deposit(&escrow_id).unwrap();
+        at_stage("bob.near", 0);
+        contract.claim_partial(escrow_id.clone(), part_secret(4), 4, proofs[4].clone(), U128(100));
+        let last = contract.internal_get_deposit(&escrow_id).unwrap();
+
+        as_callback();
+        assert!(!contract.resolve_fill(escrow_id.clone(), first, U128(25), None, Err(PromiseError::Failed)));
+        assert!(contract.resolve_fill(escrow_id.clone(), last, U128(75), Some("bob.near".parse().unwrap()), Ok(())));
+
+        let deposit = contract.internal_get_deposit(&escrow_id).unwrap();
+        assert_eq!(deposit.filled, U128(75));
+        assert!(!deposit.claimed);
+        assert_eq!(contract.get_stats().locked[0].amount, U128(25));
+    }
+
+    #[test]
+    fn failed_fill_of_a_closed_escrow_goes_back_to_the_sender() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+
+        contract.claim_partial(escrow_id.clone(), part_secret(0), 0, proofs[0].clone(), U128(25));
+        let first = contract.internal_get_deposit(&escrow_id).unwrap();
+        at_stage("bob.near", 0);
+        contract.claim_partial(escrow_id.clone(), part_secret(4), 4, proofs[4].clone(), U128(100));
+        let last = contract.internal_get_deposit(&escrow_id).unwrap();
+
+        // El último relleno se confirma y cierra el escrow antes de que falle el primero
+        as_callback();
+        assert!(contract.resolve_fill(escrow_id.clone(), last, U128(75), Some("bob.near".parse().unwrap()), Ok(())));
+        as_callback();
+        assert!(!contract.resolve_fill(escrow_id.clone(), first, U128(25), None, Err(PromiseError::Failed)));
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(function_call_args(&receipts[0])["receiver_id"], "alice.near");
+        assert_eq!(function_call_args(&receipts[0])["amount"], "25");
+        assert!(contract.get_stats().locked.is_empty());
+    }
+
+    #[test]
+    fn failed_fill_during_a_refund_is_refunded_later() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+
+        contract.claim_partial(escrow_id.clone(), part_secret(0), 0, proofs[0].clone(), U128(25));
+        let fill = contract.internal_get_deposit(&escrow_id).unwrap();
+        at_stage("carol.near", 60 * 60 * 24);
+        contract.retrieve_tokens(escrow_id.clone());
+        let refund = fill.clone();
+
+        as_callback();
+        assert!(!contract.resolve_fill(escrow_id.clone(), fill, U128(25), None, Err(PromiseError::Failed)));
+        assert!(contract.resolve_payout(escrow_id.clone(), refund, "carol.near".parse().unwrap(), Ok(())));
+
+        // Se devolvieron 75; los 25 del relleno fallido siguen en el escrow
+        let deposit = contract.internal_get_deposit(&escrow_id).unwrap();
+        assert!(!deposit.refunded);
+        assert_eq!(deposit.filled, U128(75));
+        assert_eq!(contract.get_stats().locked[0].amount, U128(25));
+
+        at_stage("carol.near", 60 * 60 * 24);
+        contract.retrieve_tokens(escrow_id);
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(function_call_args(&receipts[0])["amount"], "25");
+    }
+
+    /// Eventos NEP-297 registrados en la ejecución actual
+    fn events() -> Vec<near_sdk::serde_json::Value> {
+        near_sdk::test_utils::get_logs()
+            .iter()
+            .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+            .map(|json| near_sdk::serde_json::from_str(json).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_on_transfer_emits_create_event() {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        fund_near(&mut contract, &alice);
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id("token.near".parse().unwrap()).build());
+        contract.ft_on_transfer(alice, U128(23), msg_json(&escrow_msg(&hash, HashAlgorithm::Sha256)));
+
+        let events = events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["standard"], "fusion_escrow");
+        assert_eq!(events[0]["version"], "1.0.0");
+        assert_eq!(events[0]["event"], "escrow_create");
+        assert_eq!(events[0]["data"][0]["escrow_id"], only_escrow_id(&contract));
+        assert_eq!(events[0]["data"][0]["hashlock"], hash.as_str());
+        assert_eq!(events[0]["data"][0]["sender"], "alice.near");
+        assert_eq!(events[0]["data"][0]["amount"], "23");
+        assert_eq!(events[0]["data"][0]["asset"]["kind"], "ft");
+        assert_eq!(events[0]["data"][0]["asset"]["token_id"], "token.near");
+        assert_eq!(events[0]["data"][0]["src_chain_id"], 397);
+        assert_eq!(events[0]["data"][0]["evm_maker"], format!("0x{}", "11".repeat(20)));
+        assert_eq!(events[0]["data"][0]["dst_amount"], "1000");
+    }
+
+    #[test]
+    fn recive_near_emits_create_event() {
+        let mut contract = new_contract();
+
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        testing_env!(VMContextBuilder::new().attached_deposit(NearToken::from_near(1)).build());
+        contract.recive_near(EscrowMsg::V1(escrow_msg(&hash, HashAlgorithm::Sha256)));
+
+        let events = events();
+        assert_eq!(events[0]["event"], "escrow_create");
+        assert_eq!(events[0]["data"][0]["asset"]["kind"], "near");
+    }
+
+    #[test]
+    fn claim_tokens_emits_revealed_secret() {
+        let (mut contract, hash) = staged_contract();
+
+        at_stage("bob.near", 90);
+        contract.claim_tokens(hash.clone(), SECRET.to_uppercase());
+
+        let events = events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["event"], "escrow_claim");
+        assert_eq!(events[0]["data"][0]["escrow_id"], hash.as_str());
+        assert_eq!(events[0]["data"][0]["secret"], SECRET);
+        assert_eq!(events[0]["data"][0]["executor"], "bob.near");
+        assert_eq!(events[0]["data"][0]["recipient"], "bob.near");
+        assert!(events[0]["data"][0].get("index").is_none());
+    }
+
+    #[test]
+    fn claim_partial_emits_index() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+
+        contract.claim_partial(escrow_id, part_secret(1), 1, proofs[1].clone(), U128(30));
+
+        let events = events();
+        assert_eq!(events.last().unwrap()["event"], "escrow_claim");
+        assert_eq!(events.last().unwrap()["data"][0]["index"], 1);
+        assert_eq!(events.last().unwrap()["data"][0]["amount"], "30");
+        assert_eq!(events.last().unwrap()["data"][0]["secret"], part_secret(1));
+    }
+
+    #[test]
+    fn retrieve_tokens_emits_expire_and_refund() {
+        let (mut contract, hash) = staged_contract();
+
+        at_stage("carol.near", 250);
+        contract.retrieve_tokens(hash.clone());
+
+        let events = events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "escrow_expire");
+        assert_eq!(events[0]["data"][0]["expired_at"], (180 * 1_000_000_000u64).to_string());
+        assert_eq!(events[1]["event"], "escrow_refund");
+        assert_eq!(events[1]["data"][0]["sender"], "alice.near");
+        assert_eq!(events[1]["data"][0]["executor"], "carol.near");
+    }
+
+    #[test]
+    fn status_follows_stages() {
+        let (mut contract, hash) = staged_contract();
+        let status = |contract: &Contract| contract.get_deposits(None, None, None).deposits[0].status;
+
+        at_stage("alice.near", 10);
+        assert_eq!(status(&contract), EscrowStatus::Active);
+        at_stage("alice.near", 60);
+        assert_eq!(status(&contract), EscrowStatus::Claimable);
+        at_stage("bob.near", 180);
+        assert_eq!(status(&contract), EscrowStatus::Refundable);
+
+        contract.retrieve_tokens(hash);
+        assert_eq!(status(&contract), EscrowStatus::Refunded);
+    }
+
+    /// Tres depósitos NEP-141 de alice: dos con bob.near de taker y uno con carol.near
+    fn indexed_contract() -> (Contract, Vec<String>) {
+        let mut contract = new_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        fund_near(&mut contract, &alice);
+        testing_env!(VMContextBuilder::new().predecessor_account_id("token.near".parse().unwrap()).build());
+
+        for i in 0..3 {
+            let hash = hashlock(HashAlgorithm::Sha256, &part_secret(i));
+            let msg = EscrowMsgV1 {
+                taker: if i == 2 { "carol.near" } else { "bob.near" }.parse().unwrap(),
+                ..escrow_msg(&hash, HashAlgorithm::Sha256)
+            };
+            assert_eq!(unused(contract.ft_on_transfer(alice.clone(), U128(10), msg_json(&msg))), U128(0));
+        }
+
+        let escrow_ids = contract.deposits.keys_as_vector().to_vec();
+        (contract, escrow_ids)
+    }
+
+    #[test]
+    fn deposits_by_sender_taker_and_token() {
+        let (contract, escrow_ids) = indexed_contract();
+        let escrow_ids_of = |views: Vec<DepositView>| views.into_iter().map(|view| view.escrow_id).collect::<Vec<_>>();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        assert_eq!(escrow_ids_of(contract.get_deposits_by_sender(alice.clone(), None, None)), escrow_ids);
+        assert_eq!(escrow_ids_of(contract.get_deposits_by_sender(alice, Some(U128(1)), Some(1))), vec![escrow_ids[1].clone()]);
+        assert_eq!(escrow_ids_of(contract.get_deposits_by_taker("bob.near".parse().unwrap(), None, None)), escrow_ids[..2]);
+        assert_eq!(escrow_ids_of(contract.get_deposits_by_taker("carol.near".parse().unwrap(), None, None)), escrow_ids[2..]);
+        assert_eq!(escrow_ids_of(contract.get_deposits_by_token("token.near".parse().unwrap(), None, Some(2))), escrow_ids[..2]);
+        assert!(contract.get_deposits_by_sender("dave.near".parse().unwrap(), None, None).is_empty());
+    }
+
+    #[test]
+    fn get_deposits_filters_by_status() {
+        let (mut contract, escrow_ids) = indexed_contract();
+        let mut deposit = contract.internal_get_deposit(&escrow_ids[1]).unwrap();
+        deposit.claimed = true;
+        contract.internal_save_deposit(&escrow_ids[1], &deposit);
+
+        let claimed = contract.get_deposits(None, None, Some(EscrowStatus::Claimed));
+        assert_eq!(claimed.deposits.len(), 1);
+        assert_eq!(claimed.deposits[0].escrow_id, escrow_ids[1]);
+        assert_eq!(claimed.next_index, None);
+        assert_eq!(contract.get_deposits(None, None, Some(EscrowStatus::Claimable)).deposits.len(), 2);
+        assert_eq!(contract.get_deposits(Some(U128(2)), Some(10), None).deposits.len(), 1);
+    }
+
+    #[test]
+    fn get_deposits_fills_pages_with_matches() {
+        let (mut contract, escrow_ids) = indexed_contract();
+        let mut deposit = contract.internal_get_deposit(&escrow_ids[0]).unwrap();
+        deposit.claimed = true;
+        contract.internal_save_deposit(&escrow_ids[0], &deposit);
+        let escrow_ids_of = |page: &DepositsPage| page.deposits.iter().map(|view| view.escrow_id.clone()).collect::<Vec<_>>();
+
+        // El depósito reclamado de la posición 0 no ocupa sitio en la página
+        let page = contract.get_deposits(None, Some(1), Some(EscrowStatus::Claimable));
+        assert_eq!(escrow_ids_of(&page), vec![escrow_ids[1].clone()]);
+        assert_eq!(page.next_index, Some(U128(2)));
+
+        let page = contract.get_deposits(page.next_index, Some(1), Some(EscrowStatus::Claimable));
+        assert_eq!(escrow_ids_of(&page), vec![escrow_ids[2].clone()]);
+        assert_eq!(page.next_index, None);
+
+        // Sin filtro la página se corta en `limit` y sigue por la posición siguiente
+        let page = contract.get_deposits(None, Some(2), None);
+        assert_eq!(escrow_ids_of(&page), escrow_ids[..2]);
+        assert_eq!(page.next_index, Some(U128(2)));
+        assert!(contract.get_deposits(None, None, Some(EscrowStatus::Refunded)).deposits.is_empty());
+    }
+
+    #[test]
+    fn settled_deposit_leaves_indexes() {
+        let (mut contract, escrow_ids) = indexed_contract();
+        let deposit = contract.internal_get_deposit(&escrow_ids[2]).unwrap();
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(env::current_account_id())
+            .build());
+        assert!(contract.resolve_payout(escrow_ids[2].clone(), deposit, "carol.near".parse().unwrap(), Ok(())));
+
+        assert!(contract.get_deposits_by_taker("carol.near".parse().unwrap(), None, None).is_empty());
+        assert_eq!(contract.get_deposits_by_sender("alice.near".parse().unwrap(), None, None).len(), 2);
+    }
+
+    #[test]
+    fn escrow_id_matches_view_helper() {
+        let (contract, escrow_ids) = indexed_contract();
+        let deposit = contract.internal_get_deposit(&escrow_ids[0]).unwrap();
+
+        assert_eq!(contract.get_escrow_id(deposit.immutables()), escrow_ids[0]);
+        assert_eq!(deposit.immutables().maker, "alice.near".parse::<AccountId>().unwrap());
+    }
+
+    #[test]
+    fn stats_count_created_and_locked_value() {
+        let (contract, _) = indexed_contract();
+
+        let stats = contract.get_stats();
+        assert_eq!(stats.stats.total_created, 3);
+        assert_eq!(
+            stats.locked,
+            vec![LockedValue { asset: Asset::Ft { token_id: "token.near".parse().unwrap() }, amount: U128(30) }]
+        );
+    }
+
+    #[test]
+    fn stats_count_settled_escrows() {
+        let (mut contract, escrow_ids) = indexed_contract();
+        let claimed = DepositInfo { claimed: true, ..contract.internal_get_deposit(&escrow_ids[0]).unwrap() };
+        let refunded = DepositInfo { refunded: true, ..contract.internal_get_deposit(&escrow_ids[1]).unwrap() };
+        contract.internal_save_deposit(&escrow_ids[0], &claimed);
+        contract.internal_save_deposit(&escrow_ids[1], &refunded);
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(env::current_account_id())
+            .build());
+        let executor: AccountId = "carol.near".parse().unwrap();
+        assert!(contract.resolve_payout(escrow_ids[0].clone(), claimed, executor.clone(), Ok(())));
+        assert!(contract.resolve_payout(escrow_ids[1].clone(), refunded, executor, Ok(())));
+
+        let stats = contract.get_stats();
+        assert_eq!(stats.stats, Stats { total_created: 3, total_claimed: 1, total_refunded: 1 });
+        assert_eq!(stats.locked[0].amount, U128(10));
+    }
+
+    #[test]
+    fn partial_fills_unlock_value() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+
+        contract.claim_partial(escrow_id.clone(), part_secret(0), 0, proofs[0].clone(), U128(25));
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(env::current_account_id())
+            .build());
+        let deposit = contract.internal_get_deposit(&escrow_id).unwrap();
+        assert!(contract.resolve_fill(escrow_id, deposit, U128(25), None, Ok(())));
+
+        let stats = contract.get_stats();
+        assert_eq!(stats.locked[0].amount, U128(75));
+        assert_eq!(stats.stats.total_claimed, 0);
+    }
+
+    #[test]
+    fn init_default_timelocks_apply_to_new_deposits() {
+        let mut contract = Contract::init("owner.near".parse().unwrap(), Some(TIMELOCKS), vec![]);
+
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        testing_env!(VMContextBuilder::new().attached_deposit(NearToken::from_near(1)).build());
+        contract.recive_near(EscrowMsg::V1(escrow_msg(&hash, HashAlgorithm::Sha256)));
+
+        assert_eq!(contract.internal_get_deposit(&only_escrow_id(&contract)).unwrap().timelocks, TIMELOCKS);
+    }
+
+    #[test]
+    #[should_panic(expected = "Los timelocks deben ser crecientes")]
+    fn init_rejects_invalid_default_timelocks() {
+        Contract::init("owner.near".parse().unwrap(), Some(Timelocks { cancellation: 0, ..TIMELOCKS }), vec![]);
+    }
+
+    fn as_owner() {
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id("owner.near".parse().unwrap())
+            .attached_deposit(ONE_YOCTO)
+            .build());
+    }
+
+    #[test]
+    fn token_limits_refund_out_of_range_amounts() {
+        let mut contract = new_contract();
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let msg = msg_json(&escrow_msg(&hash, HashAlgorithm::Sha256));
+
+        as_owner();
+        contract.set_supported_token("token.near".parse().unwrap(), Some(U128(10)), Some(U128(100)));
+
+        fund_near(&mut contract, &alice);
+        assert_eq!(unused(contract.ft_on_transfer(alice.clone(), U128(9), msg.clone())), U128(9));
+        assert_eq!(unused(contract.ft_on_transfer(alice.clone(), U128(101), msg.clone())), U128(101));
+        assert_eq!(contract.deposits.len(), 0);
+
+        assert_eq!(unused(contract.ft_on_transfer(alice, U128(100), msg)), U128(0));
+        assert_eq!(contract.deposits.len(), 1);
+    }
+
+    #[test]
+    fn removed_token_is_refunded() {
+        let mut contract = new_contract();
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        as_owner();
+        contract.remove_supported_token("token.near".parse().unwrap());
+        assert!(contract.get_config().supported_tokens.is_empty());
+
+        fund_near(&mut contract, &alice);
+        let msg = msg_json(&escrow_msg(&hash, HashAlgorithm::Sha256));
+        assert_eq!(unused(contract.ft_on_transfer(alice, U128(23), msg)), U128(23));
+    }
+
+    #[test]
+    #[should_panic(expected = "Sólo el owner puede hacer esto")]
+    fn set_supported_token_only_owner() {
+        let mut contract = new_contract();
+
+        testing_env!(VMContextBuilder::new().attached_deposit(ONE_YOCTO).build());
+        contract.set_supported_token("other.near".parse().unwrap(), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "El máximo del token debe ser mayor o igual que el mínimo")]
+    fn set_supported_token_rejects_inverted_limits() {
+        let mut contract = new_contract();
+
+        as_owner();
+        contract.set_supported_token("other.near".parse().unwrap(), Some(U128(10)), Some(U128(1)));
+    }
+
+    #[test]
+    fn pause_refunds_new_ft_deposits() {
+        let mut contract = new_contract();
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        as_owner();
+        contract.pause();
+        assert!(contract.get_config().paused);
+
+        fund_near(&mut contract, &alice);
+        let msg = msg_json(&escrow_msg(&hash, HashAlgorithm::Sha256));
+        assert_eq!(unused(contract.ft_on_transfer(alice.clone(), U128(23), msg.clone())), U128(23));
+
+        as_owner();
+        contract.unpause();
+        from_token();
+        assert_eq!(unused(contract.ft_on_transfer(alice, U128(23), msg)), U128(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "El contrato está pausado")]
+    fn pause_blocks_recive_near() {
+        let mut contract = new_contract();
+
+        as_owner();
+        contract.pause();
+
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        testing_env!(VMContextBuilder::new().attached_deposit(NearToken::from_near(1)).build());
+        contract.recive_near(EscrowMsg::V1(escrow_msg(&hash, HashAlgorithm::Sha256)));
+    }
+
+    #[test]
+    fn claims_and_refunds_work_while_paused() {
+        let (mut contract, hash) = staged_contract();
+        let other = hashlock(HashAlgorithm::Sha256, &part_secret(0));
+        contract.internal_save_deposit(&other, &contract.internal_get_deposit(&hash).unwrap());
+
+        as_owner();
+        contract.pause();
+
+        at_stage("bob.near", 90);
+        contract.claim_tokens(hash.clone(), SECRET.to_string());
+        assert!(contract.internal_get_deposit(&hash).unwrap().claimed);
+
+        at_stage("bob.near", 200);
+        contract.retrieve_tokens(other.clone());
+        assert!(contract.internal_get_deposit(&other).unwrap().refunded);
+    }
+
+    #[test]
+    #[should_panic(expected = "Sólo el owner puede hacer esto")]
+    fn pause_only_owner() {
+        let mut contract = new_contract();
+
+        testing_env!(VMContextBuilder::new().attached_deposit(ONE_YOCTO).build());
+        contract.pause();
+    }
+
+    #[test]
+    fn ownership_transfer_takes_two_steps() {
+        let mut contract = new_contract();
+        let carol: AccountId = "carol.near".parse().unwrap();
+
+        as_owner();
+        contract.propose_owner(carol.clone());
+        assert_eq!(contract.get_config().owner_id, "owner.near".parse::<AccountId>().unwrap());
+        assert_eq!(contract.get_config().pending_owner_id, Some(carol.clone()));
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(carol.clone())
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.accept_ownership();
+
+        let config = contract.get_config();
+        assert_eq!(config.owner_id, carol);
+        assert_eq!(config.pending_owner_id, None);
+
+        let events = events();
+        assert_eq!(events[0]["event"], "ownership_transferred");
+        assert_eq!(events[0]["data"][0]["old_owner"], "owner.near");
+        assert_eq!(events[0]["data"][0]["new_owner"], "carol.near");
+    }
+
+    #[test]
+    #[should_panic(expected = "Sólo el owner propuesto puede aceptar")]
+    fn accept_ownership_only_pending_owner() {
+        let mut contract = new_contract();
+
+        as_owner();
+        contract.propose_owner("carol.near".parse().unwrap());
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id("dave.near".parse().unwrap())
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.accept_ownership();
+    }
+
+    #[test]
+    fn admin_actions_emit_events() {
+        let mut contract = new_contract();
+
+        as_owner();
+        contract.pause();
+        contract.set_supported_token("other.near".parse().unwrap(), Some(U128(5)), None);
+        contract.remove_supported_token("other.near".parse().unwrap());
+
+        let events = events();
+        assert_eq!(events[0]["event"], "pause_changed");
+        assert_eq!(events[0]["data"][0]["paused"], true);
+        assert_eq!(events[1]["event"], "token_set");
+        assert_eq!(events[1]["data"][0]["min_amount"], "5");
+        assert_eq!(events[2]["event"], "token_removed");
+        assert_eq!(events[2]["data"][0]["token_id"], "other.near");
+    }
+
+    /// Guarda el estado tal y como lo dejaba el contrato original, con
+    /// depósitos de 1000 de alice.near
+    fn write_v1_state(deposits: &[(&str, bool)]) {
+        use crate::migration::{ContractV1, DepositInfoV1};
+
+        testing_env!(VMContextBuilder::new().build());
+        let mut old = ContractV1 { deposits: UnorderedMap::new(0u8), deposit_number: U128(deposits.len() as u128) };
+        for (hash, claimed) in deposits {
+            let deposit = DepositInfoV1 { sender: "alice.near".parse().unwrap(), amount: U128(1000), timestamp: 0, claimed: *claimed };
+            old.deposits.insert(&hash.to_string(), &deposit);
+        }
+        env::state_write(&old);
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(env::current_account_id())
+            .build());
+    }
+
+    #[test]
+    fn migrate_v1_snapshot() {
+        use crate::migration::{read_state_version, STATE_VERSION};
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let ft_hash = hashlock(HashAlgorithm::Sha256, &part_secret(1));
+        let claimed_hash = hashlock(HashAlgorithm::Sha256, &part_secret(0));
+
+        write_v1_state(&[(&hash, false), (&ft_hash, false), (&claimed_hash, true)]);
+        assert_eq!(read_state_version(), None);
+
+        let supported = SupportedToken { token_id: "token.near".parse().unwrap(), limits: TokenLimits::default() };
+        // El reclamado no necesita activo
+        let v1_assets = vec![(hash.clone(), Asset::Near), (ft_hash.clone(), token())];
+        let mut contract = Contract::migrate("owner.near".parse().unwrap(), vec![supported], v1_assets);
+        assert_eq!(read_state_version(), Some(STATE_VERSION));
+
+        // Mismas claves, con los campos nuevos rellenados
+        let deposit = contract.internal_get_deposit(&hash).unwrap();
+        assert_eq!(deposit.sender, alice);
+        assert_eq!(deposit.amount, U128(1000));
+        assert_eq!(deposit.hashlock, hash);
+        assert_eq!(deposit.asset, Asset::Near);
+        assert_eq!(deposit.taker, alice);
+        assert_eq!(deposit.timelocks, Timelocks::DEFAULT);
+        // El que llegó por ft_on_transfer se queda con el token indicado
+        assert_eq!(contract.internal_get_deposit(&ft_hash).unwrap().asset, token());
+        assert_eq!(contract.get_deposits_by_token("token.near".parse().unwrap(), None, None).len(), 1);
+        // El reclamado ya se pagó y no se conserva
+        assert!(contract.internal_get_deposit(&claimed_hash).is_none());
+
+        assert_eq!(contract.get_config().owner_id, "owner.near".parse::<AccountId>().unwrap());
+        let stats = contract.get_stats();
+        assert_eq!(stats.stats, Stats { total_created: 3, total_claimed: 1, total_refunded: 0 });
+        assert_eq!(
+            stats.locked,
+            vec![LockedValue { asset: Asset::Near, amount: U128(1000) }, LockedValue { asset: token(), amount: U128(1000) }]
+        );
+        assert_eq!(contract.get_deposits_by_sender(alice.clone(), None, None).len(), 2);
+
+        // El hashlock de un depósito ya reclamado no se puede volver a usar
+        assert!(contract.get_hashlock_revealed_at(claimed_hash).is_some());
+        assert!(contract.get_hashlock_revealed_at(hash.clone()).is_none());
+
+        // El depósito migrado se reclama con el secreto de su hash
+        at_stage("alice.near", 0);
+        contract.claim_tokens(hash.clone(), SECRET.to_string());
+        assert!(contract.internal_get_deposit(&hash).unwrap().claimed);
+
+        // El de NEP-141 se paga con ft_transfer, nunca en NEAR
+        at_stage("alice.near", 0);
+        contract.claim_tokens(ft_hash, part_secret(1));
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, "token.near".parse::<AccountId>().unwrap());
+        assert_eq!(function_call_args(&receipts[0])["amount"], "1000");
+    }
+
+    #[test]
+    #[should_panic(expected = "Falta el activo de un depósito de V1")]
+    fn migrate_v1_requires_the_asset_of_every_deposit() {
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let ft_hash = hashlock(HashAlgorithm::Sha256, &part_secret(1));
+
+        write_v1_state(&[(&hash, false), (&ft_hash, false)]);
+        Contract::migrate("owner.near".parse().unwrap(), vec![], vec![(hash, Asset::Near)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Hay activos de depósitos que no existen en V1")]
+    fn migrate_v1_rejects_assets_of_unknown_deposits() {
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        write_v1_state(&[(&hash, false)]);
+        let v1_assets = vec![(hash, Asset::Near), (ORDER_HASH.to_string(), Asset::Near)];
+        Contract::migrate("owner.near".parse().unwrap(), vec![], v1_assets);
+    }
+
+    #[test]
+    fn migrate_current_state_is_a_no_op() {
+        use crate::migration::{read_state_version, STATE_VERSION};
+
+        let (contract, escrow_ids) = indexed_contract();
+        env::state_write(&contract);
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(env::current_account_id())
+            .build());
+        let migrated = Contract::migrate("carol.near".parse().unwrap(), vec![], vec![]);
+
+        assert_eq!(migrated.get_config().owner_id, "owner.near".parse::<AccountId>().unwrap());
+        assert_eq!(migrated.deposits.keys_as_vector().to_vec(), escrow_ids);
+        assert_eq!(migrated.get_stats().stats.total_created, 3);
+        assert_eq!(read_state_version(), Some(STATE_VERSION));
+    }
+
+    #[test]
+    #[should_panic(expected = "Versión del estado desconocida")]
+    fn migrate_rejects_an_unknown_state_version() {
+        use crate::migration::STATE_VERSION;
+
+        let contract = new_contract();
+        env::state_write(&contract);
+        env::storage_write(b"STATE_VERSION", &[STATE_VERSION + 1]);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(env::current_account_id()).build());
+        Contract::migrate("owner.near".parse().unwrap(), vec![], vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "El estado no corresponde con su versión")]
+    fn migrate_reads_the_layout_of_the_tag() {
+        use crate::migration::ContractV1;
+
+        // Estado de V1 con la etiqueta de la versión actual: no se prueba con otros layouts
+        testing_env!(VMContextBuilder::new().build());
+        env::state_write(&ContractV1 { deposits: UnorderedMap::new(0u8), deposit_number: U128(0) });
+        write_state_version();
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(env::current_account_id()).build());
+        Contract::migrate("owner.near".parse().unwrap(), vec![], vec![]);
+    }
+
+    /// NEAR que el contrato necesita en el contexto actual: el stake del
+    /// almacenamiento, lo bloqueado en escrows de NEAR y lo que debe, que
+    /// incluye los depósitos de seguridad y el prepago de los hashes revelados.
+    fn near_needed(contract: &Contract) -> u128 {
+        u128::from(env::storage_usage()) * env::storage_byte_cost().as_yoctonear()
+            + contract.locked_value.get(&Asset::Near).unwrap_or(0)
+            + contract.near_liabilities
+    }
+
+    /// El owner propone rescatar `amount` de `asset` en el instante 0.
+    fn propose(contract: &mut Contract, asset: &Asset, amount: u128) {
+        as_owner();
+        contract.propose_rescue(asset.clone(), U128(amount));
+    }
+
+    /// Contexto de `execute_rescue` pasado el retraso de rescate desde una
+    /// propuesta en el instante 0. El saldo de la cuenta es justo lo que el
+    /// contrato necesita más `surplus`, contando el yoctoNEAR adjunto, que ya
+    /// forma parte del saldo.
+    fn rescue_context(contract: &Contract, predecessor: &str, surplus: u128) {
+        let rescue_at = u64::from(contract.rescue_delay) * 1_000_000_000;
+        testing_env!(VMContextBuilder::new().block_timestamp(rescue_at).build());
+        let needed = near_needed(contract);
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(predecessor.parse().unwrap())
+            .attached_deposit(ONE_YOCTO)
+            .block_timestamp(rescue_at)
+            .account_balance(NearToken::from_yoctonear(needed + surplus - 1))
+            .build());
+    }
+
+    fn near_escrow_contract() -> (Contract, String) {
+        let mut contract = new_contract();
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id("alice.near".parse().unwrap())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        let msg = EscrowMsgV1 { safety_deposit: Some(U128(500)), ..escrow_msg(&hashlock(HashAlgorithm::Sha256, SECRET), HashAlgorithm::Sha256) };
+        contract.recive_near(EscrowMsg::V1(msg));
+        // El depósito de seguridad y el prepago del hashlock
+        let revealed_deposit = contract.internal_revealed_entry_cost();
+        assert_eq!(contract.near_liabilities, 500 + revealed_deposit);
+
+        let escrow_id = only_escrow_id(&contract);
+        (contract, escrow_id)
+    }
+
+    #[test]
+    fn rescue_near_surplus_after_delay() {
+        let (mut contract, escrow_id) = near_escrow_contract();
+
+        propose(&mut contract, &Asset::Near, 5);
+        assert_eq!(events()[0]["event"], "rescue_proposed");
+        assert_eq!(events()[0]["data"][0]["amount"], "5");
+        assert_eq!(
+            contract.get_rescue_proposal(Asset::Near),
+            Some(RescueProposal { amount: U128(5), proposed_at: 0, executing: false })
+        );
+
+        rescue_context(&contract, "owner.near", 5);
+        contract.execute_rescue(Asset::Near);
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, "owner.near".parse::<AccountId>().unwrap());
+        assert!(matches!(
+            receipts[0].actions[0],
+            near_sdk::mock::MockAction::Transfer { deposit, .. } if deposit == NearToken::from_yoctonear(5)
+        ));
+        assert_eq!(events()[0]["event"], "funds_rescued");
+        assert_eq!(events()[0]["data"][0]["amount"], "5");
+        assert_eq!(contract.get_rescue_proposal(Asset::Near), None);
+
+        // El escrow no se toca
+        assert!(!contract.internal_get_deposit(&escrow_id).unwrap().claimed);
+    }
+
+    #[test]
+    fn rescue_without_escrows() {
+        let mut contract = new_contract();
+
+        propose(&mut contract, &Asset::Near, 5);
+        rescue_context(&contract, "owner.near", 5);
+        contract.execute_rescue(Asset::Near);
+        assert_eq!(near_sdk::test_utils::get_created_receipts().len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Sólo se puede rescatar el excedente de los escrows activos")]
+    fn rescue_near_above_surplus() {
+        let (mut contract, _) = near_escrow_contract();
+
+        propose(&mut contract, &Asset::Near, 6);
+        rescue_context(&contract, "owner.near", 5);
+        contract.execute_rescue(Asset::Near);
+    }
+
+    #[test]
+    #[should_panic(expected = "Sólo se puede rescatar el excedente de los escrows activos")]
+    fn rescue_checks_surplus_when_executed() {
+        let (mut contract, _) = near_escrow_contract();
+
+        // Al proponerlo había 5 de excedente; al ejecutarlo sólo quedan 4
+        propose(&mut contract, &Asset::Near, 5);
+        rescue_context(&contract, "owner.near", 4);
+        contract.execute_rescue(Asset::Near);
+    }
+
+    #[test]
+    #[should_panic(expected = "El periodo de rescate aún no ha pasado")]
+    fn rescue_before_delay() {
+        let (mut contract, _) = near_escrow_contract();
+
+        propose(&mut contract, &Asset::Near, 1);
+        contract.execute_rescue(Asset::Near);
+    }
+
+    #[test]
+    #[should_panic(expected = "El periodo de rescate aún no ha pasado")]
+    fn rescue_delay_counts_from_the_proposal() {
+        let (mut contract, _) = near_escrow_contract();
+
+        // Un escrow antiguo no adelanta el rescate
+        rescue_context(&contract, "owner.near", 5);
+        contract.propose_rescue(Asset::Near, U128(1));
+        contract.execute_rescue(Asset::Near);
+    }
+
+    #[test]
+    #[should_panic(expected = "El periodo de rescate aún no ha pasado")]
+    fn proposing_again_restarts_the_delay() {
+        let (mut contract, _) = near_escrow_contract();
+
+        propose(&mut contract, &Asset::Near, 1);
+        rescue_context(&contract, "owner.near", 5);
+        contract.propose_rescue(Asset::Near, U128(2));
+        contract.execute_rescue(Asset::Near);
+    }
+
+    #[test]
+    #[should_panic(expected = "No hay ningún rescate propuesto para ese activo")]
+    fn rescue_requires_a_proposal() {
+        let (mut contract, _) = near_escrow_contract();
+
+        rescue_context(&contract, "owner.near", 5);
+        contract.execute_rescue(Asset::Near);
+    }
+
+    #[test]
+    #[should_panic(expected = "Sólo el owner puede hacer esto")]
+    fn propose_rescue_only_owner() {
+        let (mut contract, _) = near_escrow_contract();
+
+        // Ni siquiera el taker del escrow
+        rescue_context(&contract, "bob.near", 5);
+        contract.propose_rescue(Asset::Near, U128(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Sólo el owner puede hacer esto")]
+    fn execute_rescue_only_owner() {
+        let (mut contract, _) = near_escrow_contract();
+
+        propose(&mut contract, &Asset::Near, 1);
+        rescue_context(&contract, "bob.near", 5);
+        contract.execute_rescue(Asset::Near);
+    }
+
+    #[test]
+    fn rescue_delay_is_configurable() {
+        let (mut contract, _) = near_escrow_contract();
+
+        as_owner();
+        contract.set_rescue_delay(60);
+        assert_eq!(contract.get_config().rescue_delay, 60);
+        assert_eq!(events()[0]["event"], "rescue_delay_set");
+
+        propose(&mut contract, &Asset::Near, 1);
+        rescue_context(&contract, "owner.near", 1);
+        assert_eq!(env::block_timestamp(), 60 * 1_000_000_000);
+        contract.execute_rescue(Asset::Near);
+        assert_eq!(near_sdk::test_utils::get_created_receipts().len(), 1);
+    }
+
+    #[test]
+    fn claim_after_rescue_has_storage_for_the_revealed_hash() {
+        let (mut contract, escrow_id) = near_escrow_contract();
+        contract.rescue_delay = Timelocks::DEFAULT.withdrawal;
+
+        // Se rescata todo el excedente
+        propose(&mut contract, &Asset::Near, 5);
+        rescue_context(&contract, "owner.near", 5);
+        contract.execute_rescue(Asset::Near);
+        // Sólo queda de más el almacenamiento que liberó la propuesta
+        let balance = env::account_balance().as_yoctonear();
+        assert!(balance >= near_needed(&contract));
+
+        // Guardar el hash revelado gasta el prepago, que no salió con el rescate
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id("bob.near".parse().unwrap())
+            .block_timestamp(u64::from(Timelocks::DEFAULT.withdrawal) * 1_000_000_000)
+            .account_balance(NearToken::from_yoctonear(balance))
+            .build());
+        contract.claim_tokens(escrow_id.clone(), SECRET.to_string());
+        let deposit = contract.internal_get_deposit(&escrow_id).unwrap();
+        assert!(contract.get_hashlock_revealed_at(deposit.hashlock).is_some());
+        // El pago ya salió del saldo pero sigue bloqueado hasta el callback
+        assert!(env::account_balance().as_yoctonear() + deposit.amount.0 >= near_needed(&contract));
+    }
+
+    #[test]
+    fn near_liabilities_follow_balances_and_safety_deposits() {
+        let (mut contract, escrow_id) = near_escrow_contract();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        fund_near(&mut contract, &alice);
+        let balance = contract.get_near_balance(alice.clone()).0;
+        let revealed_deposit = contract.internal_revealed_entry_cost();
+        assert_eq!(contract.near_liabilities, 500 + revealed_deposit + balance);
+
+        at_stage("bob.near", Timelocks::DEFAULT.withdrawal.into());
+        contract.claim_tokens(escrow_id.clone(), SECRET.to_string());
+        let deposit = contract.internal_get_deposit(&escrow_id).unwrap();
+        testing_env!(VMContextBuilder::new().predecessor_account_id(env::current_account_id()).build());
+        contract.resolve_payout(escrow_id, deposit, "bob.near".parse().unwrap(), Ok(()));
+        assert_eq!(contract.near_liabilities, balance);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(alice).attached_deposit(ONE_YOCTO).build());
+        contract.withdraw_near(U128(balance));
+        assert_eq!(contract.near_liabilities, 0);
+    }
+
+    #[test]
+    fn rescue_ft_checks_balance_in_callback() {
+        let (mut contract, _) = indexed_contract();
+        let token = token();
+
+        propose(&mut contract, &token, 5);
+        rescue_context(&contract, "owner.near", 1);
+        contract.execute_rescue(token.clone());
+        assert!(contract.get_rescue_proposal(token.clone()).unwrap().executing);
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(receipts[0].receiver_id, "token.near".parse::<AccountId>().unwrap());
+        assert!(matches!(
+            &receipts[0].actions[0],
+            near_sdk::mock::MockAction::FunctionCallWeight { method_name, .. } if method_name == b"ft_balance_of"
+        ));
+
+        // 30 bloqueados en los tres escrows, 5 de excedente
+        testing_env!(VMContextBuilder::new().predecessor_account_id(env::current_account_id()).build());
+        let result = contract.resolve_rescue(token.clone(), "owner.near".parse().unwrap(), U128(5), Ok(U128(35)));
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        drop(result);
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(receipts[0].receiver_id, "token.near".parse::<AccountId>().unwrap());
+        let args = function_call_args(&receipts[0]);
+        assert_eq!(args["receiver_id"], "owner.near");
+        assert_eq!(args["amount"], "5");
+
+        // La propuesta sigue hasta que termina la transferencia
+        assert!(contract.get_rescue_proposal(token.clone()).is_some());
+        assert!(contract.resolve_rescue_transfer(token.clone(), Ok(())));
+        assert_eq!(contract.get_rescue_proposal(token), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Ya hay un rescate en curso de ese activo")]
+    fn rescue_ft_one_at_a_time() {
+        let (mut contract, _) = indexed_contract();
+
+        propose(&mut contract, &token(), 5);
+        rescue_context(&contract, "owner.near", 1);
+        contract.execute_rescue(token());
+        contract.execute_rescue(token());
+    }
+
+    #[test]
+    #[should_panic(expected = "Ya hay un rescate en curso de ese activo")]
+    fn rescue_ft_cannot_be_replaced_while_executing() {
+        let (mut contract, _) = indexed_contract();
+
+        propose(&mut contract, &token(), 5);
+        rescue_context(&contract, "owner.near", 1);
+        contract.execute_rescue(token());
+        contract.propose_rescue(token(), U128(1));
+    }
+
+    #[test]
+    fn rescue_ft_above_surplus_keeps_the_proposal() {
+        let (mut contract, _) = indexed_contract();
+        let token = token();
+
+        propose(&mut contract, &token, 6);
+        rescue_context(&contract, "owner.near", 1);
+        contract.execute_rescue(token.clone());
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(env::current_account_id()).build());
+        let result = contract.resolve_rescue(token.clone(), "owner.near".parse().unwrap(), U128(6), Ok(U128(35)));
+        assert!(matches!(result, PromiseOrValue::Value(false)));
+        assert!(near_sdk::test_utils::get_created_receipts().is_empty());
+        assert!(!contract.get_rescue_proposal(token.clone()).unwrap().executing);
+
+        // Se puede volver a ejecutar cuando haya excedente
+        rescue_context(&contract, "owner.near", 1);
+        contract.execute_rescue(token.clone());
+        assert!(contract.get_rescue_proposal(token).unwrap().executing);
+    }
+
+    /// Depósito de 10_000 token.near con un 0,3% para el protocolo y un 0,2% para integrator.near
+    fn fee_deposit(hash: &str) -> DepositInfo {
+        DepositInfo {
+            amount: U128(10_000),
+            protocol_fee_bps: 30,
+            integrator_fee: Some(IntegratorFee { recipient: "integrator.near".parse().unwrap(), fee_bps: 20 }),
+            ..ft_deposit_info("alice.near".parse().unwrap(), hash)
+        }
+    }
+
+    fn token() -> Asset {
+        Asset::Ft { token_id: "token.near".parse().unwrap() }
+    }
+
+    fn fee_balance(amount: u128) -> Vec<FeeBalance> {
+        vec![FeeBalance { asset: token(), amount: U128(amount) }]
+    }
+
+    #[test]
+    fn claim_deducts_fees_and_accrues_them_on_success() {
+        let mut contract = new_contract();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let deposit = fee_deposit(&hash);
+        contract.internal_save_deposit(&hash, &deposit);
+        contract.internal_lock(&deposit.asset, deposit.amount.0);
+
+        contract.claim_tokens(hash.clone(), SECRET.to_string());
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(function_call_args(&receipts[0])["amount"], "9950");
+        let event = &events()[0]["data"][0];
+        assert_eq!(event["amount"], "10000");
+        assert_eq!(event["protocol_fee"], "30");
+        assert_eq!(event["integrator_fee"], "20");
+
+        // Hasta que se confirma el pago no hay nada que retirar
+        assert!(contract.get_protocol_fees().is_empty());
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(env::current_account_id()).build());
+        assert!(contract.resolve_payout(hash, deposit, "bob.near".parse().unwrap(), Ok(())));
+
+        assert_eq!(contract.get_protocol_fees(), fee_balance(30));
+        assert_eq!(contract.get_integrator_fees("integrator.near".parse().unwrap()), fee_balance(20));
+        assert_eq!(contract.fees_owed.get(&token()), Some(50));
+        assert_eq!(contract.internal_held(&token()), 50);
+    }
+
+    #[test]
+    fn refund_does_not_charge_fees() {
+        let mut contract = new_contract();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let deposit = fee_deposit(&hash);
+        contract.internal_save_deposit(&hash, &DepositInfo { refunded: true, ..deposit.clone() });
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(env::current_account_id()).build());
+        assert!(contract.resolve_payout(hash, deposit, "alice.near".parse().unwrap(), Ok(())));
+
+        assert!(contract.get_protocol_fees().is_empty());
+        assert!(contract.get_integrator_fees("integrator.near".parse().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn partial_fill_charges_fees_on_released_amount() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+        let deposit = contract.internal_get_deposit(&escrow_id).unwrap();
+        contract.internal_save_deposit(&escrow_id, &DepositInfo { protocol_fee_bps: 400, ..deposit });
+
+        contract.claim_partial(escrow_id.clone(), part_secret(0), 0, proofs[0].clone(), U128(25));
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(function_call_args(&receipts[0])["amount"], "24");
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(env::current_account_id()).build());
+        let deposit = contract.internal_get_deposit(&escrow_id).unwrap();
+        assert!(contract.resolve_fill(escrow_id, deposit, U128(25), None, Ok(())));
+
+        assert_eq!(contract.get_protocol_fees(), fee_balance(1));
+    }
+
+    /// Registra a integrator.near para `token()` adjuntando 1 NEAR
+    fn register_integrator(contract: &mut Contract) {
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id("integrator.near".parse().unwrap())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.register_integrator(None, token());
+    }
+
+    #[test]
+    fn register_integrator_charges_its_ledger_entry() {
+        let mut contract = new_contract();
+        let integrator: AccountId = "integrator.near".parse().unwrap();
+        assert!(!contract.is_integrator_registered(integrator.clone(), token()));
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(integrator.clone())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        let initial_storage = env::storage_usage();
+        contract.register_integrator(None, token());
+        let storage_cost = u128::from(env::storage_usage() - initial_storage) * env::storage_byte_cost().as_yoctonear();
+        assert!(storage_cost > 0);
+        assert!(contract.is_integrator_registered(integrator.clone(), token()));
+        assert_eq!(contract.get_integrator_fees(integrator), fee_balance(0));
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert!(matches!(
+            receipts[0].actions[0],
+            near_sdk::mock::MockAction::Transfer { deposit, .. }
+                if deposit == NearToken::from_yoctonear(NearToken::from_near(1).as_yoctonear() - storage_cost)
+        ));
+
+        // Registrarse otra vez no ocupa nada y se devuelve todo
+        register_integrator(&mut contract);
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert!(matches!(
+            receipts[0].actions[0],
+            near_sdk::mock::MockAction::Transfer { deposit, .. } if deposit == NearToken::from_near(1)
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "El NEAR adjunto no cubre el almacenamiento")]
+    fn register_integrator_without_deposit() {
+        let mut contract = new_contract();
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id("integrator.near".parse().unwrap()).build());
+        contract.register_integrator(None, token());
+    }
+
+    #[test]
+    fn unregistered_integrator_fee_is_refunded() {
+        let mut contract = new_contract();
+        let alice: AccountId = "alice.near".parse().unwrap();
+        fund_near(&mut contract, &alice);
+
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let msg = EscrowMsgV1 {
+            integrator_fee: Some(IntegratorFee { recipient: "integrator.near".parse().unwrap(), fee_bps: 20 }),
+            ..escrow_msg(&hash, HashAlgorithm::Sha256)
+        };
+        assert_eq!(unused(contract.ft_on_transfer(alice.clone(), U128(100), msg_json(&msg))), U128(100));
+        assert_eq!(contract.deposits.len(), 0);
+
+        register_integrator(&mut contract);
+        from_token();
+        assert_eq!(unused(contract.ft_on_transfer(alice, U128(100), msg_json(&msg))), U128(0));
+        assert_eq!(contract.deposits.len(), 1);
+    }
+
+    #[test]
+    fn owner_and_integrator_withdraw_their_fees() {
+        let mut contract = new_contract();
+        register_integrator(&mut contract);
+        contract.internal_credit_fees(None, &token(), 30);
+        contract.internal_credit_fees(Some(&"integrator.near".parse().unwrap()), &token(), 20);
+
+        as_owner();
+        contract.withdraw_fees(token(), U128(10));
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        let args = function_call_args(&receipts[0]);
+        assert_eq!(args["receiver_id"], "owner.near");
+        assert_eq!(args["amount"], "10");
+        assert_eq!(events()[0]["event"], "fees_withdrawn");
+        assert_eq!(contract.get_protocol_fees(), fee_balance(20));
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id("integrator.near".parse().unwrap())
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.withdraw_integrator_fees(token(), U128(20));
+        // La entrada pagada al registrarse se queda a cero
+        assert_eq!(contract.get_integrator_fees("integrator.near".parse().unwrap()), fee_balance(0));
+        assert_eq!(contract.fees_owed.get(&token()), Some(20));
+    }
+
+    #[test]
+    fn failed_fee_withdrawal_credits_the_ledger_again() {
+        let mut contract = new_contract();
+        let integrator: AccountId = "integrator.near".parse().unwrap();
+        contract.internal_credit_fees(Some(&integrator), &token(), 20);
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(integrator.clone())
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.withdraw_integrator_fees(token(), U128(20));
+        assert!(contract.fees_owed.is_empty());
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(env::current_account_id()).build());
+        assert!(!contract.resolve_fee_withdrawal(Some(integrator.clone()), token(), U128(20), Err(PromiseError::Failed)));
+        assert_eq!(contract.get_integrator_fees(integrator), fee_balance(20));
+        assert_eq!(contract.fees_owed.get(&token()), Some(20));
+    }
+
+    #[test]
+    #[should_panic(expected = "No hay suficientes comisiones acumuladas")]
+    fn withdraw_fees_above_accrued() {
+        let mut contract = new_contract();
+        contract.internal_credit_fees(None, &token(), 30);
+
+        as_owner();
+        contract.withdraw_fees(token(), U128(31));
+    }
+
+    #[test]
+    #[should_panic(expected = "Sólo el owner puede hacer esto")]
+    fn withdraw_fees_only_owner() {
+        let mut contract = new_contract();
+        contract.internal_credit_fees(None, &token(), 30);
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id("integrator.near".parse().unwrap())
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.withdraw_fees(token(), U128(30));
+    }
+
+    #[test]
+    fn protocol_fee_applies_to_new_escrows() {
+        let mut contract = new_contract();
+
+        as_owner();
+        contract.set_protocol_fee(25);
+        assert_eq!(contract.get_config().protocol_fee_bps, 25);
+        assert_eq!(events()[0]["data"][0]["protocol_fee_bps"], 25);
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        fund_near(&mut contract, &alice);
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        contract.ft_on_transfer(alice, U128(100), msg_json(&escrow_msg(&hash, HashAlgorithm::Sha256)));
+        assert_eq!(contract.internal_get_deposit(&only_escrow_id(&contract)).unwrap().protocol_fee_bps, 25);
+    }
+
+    #[test]
+    #[should_panic(expected = "La comisión del protocolo supera el máximo")]
+    fn protocol_fee_above_max() {
+        let mut contract = new_contract();
+
+        as_owner();
+        contract.set_protocol_fee(MAX_FEE_BPS + 1);
+    }
+
+    #[test]
+    fn integrator_fee_above_max_is_refunded() {
+        let mut contract = new_contract();
+        let alice: AccountId = "alice.near".parse().unwrap();
+        fund_near(&mut contract, &alice);
+
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let msg = EscrowMsgV1 {
+            integrator_fee: Some(IntegratorFee { recipient: "integrator.near".parse().unwrap(), fee_bps: MAX_FEE_BPS + 1 }),
+            ..escrow_msg(&hash, HashAlgorithm::Sha256)
+        };
+        assert_eq!(unused(contract.ft_on_transfer(alice, U128(100), msg_json(&msg))), U128(100));
+        assert_eq!(contract.deposits.len(), 0);
+    }
+
+    #[test]
+    fn claim_many_reports_each_item_and_groups_transfers() {
+        let (mut contract, escrow_ids) = indexed_contract();
+
+        at_stage("bob.near", 0);
+        let results = contract.claim_many(vec![
+            (escrow_ids[0].clone(), part_secret(0)),
+            (escrow_ids[1].clone(), part_secret(0)),
+            (escrow_ids[1].clone(), part_secret(1)),
+            (escrow_ids[2].clone(), part_secret(2)),
+            (escrow_ids[0].clone(), part_secret(0)),
+            ("00".repeat(32), part_secret(0)),
+        ]);
+
+        let errors: Vec<Option<&str>> = results.iter().map(|result| result.error.as_deref()).collect();
+        assert_eq!(errors, vec![
+            None,
+            Some("El secreto no corresponde con el hashlock"),
+            None,
+            None,
+            Some("Ya fueron reclamados"),
+            Some("No hay depósito para ese escrow_id"),
+        ]);
+        assert_eq!(results.iter().filter(|result| result.success).count(), 3);
+
+        // Una transferencia de 20 a bob.near y otra de 10 a carol.near, cada una con su callback
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts.len(), 4);
+        let transfers: Vec<(String, String)> = receipts
+            .iter()
+            .filter(|receipt| receipt.receiver_id == "token.near".parse::<AccountId>().unwrap())
+            .map(|receipt| {
+                let args = function_call_args(receipt);
+                (args["receiver_id"].as_str().unwrap().to_string(), args["amount"].as_str().unwrap().to_string())
+            })
+            .collect();
+        assert_eq!(transfers, vec![("bob.near".to_string(), "20".to_string()), ("carol.near".to_string(), "10".to_string())]);
+        assert_eq!(events().iter().filter(|event| event["event"] == "escrow_claim").count(), 3);
+    }
+
+    #[test]
+    fn resolve_batch_payout_settles_or_restores_every_deposit() {
+        let (mut contract, escrow_ids) = indexed_contract();
+
+        at_stage("bob.near", 0);
+        contract.claim_many(vec![(escrow_ids[0].clone(), part_secret(0)), (escrow_ids[1].clone(), part_secret(1))]);
+        let group: Vec<(String, DepositInfo)> = escrow_ids[..2]
+            .iter()
+            .map(|escrow_id| {
+                let deposit = contract.internal_get_deposit(escrow_id).unwrap();
+                (escrow_id.clone(), DepositInfo { claimed: false, ..deposit })
+            })
+            .collect();
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(env::current_account_id()).build());
+        assert!(!contract.resolve_batch_payout(group.clone(), "bob.near".parse().unwrap(), false, Err(PromiseError::Failed)));
+        assert!(escrow_ids[..2].iter().all(|escrow_id| !contract.internal_get_deposit(escrow_id).unwrap().claimed));
+
+        assert!(contract.resolve_batch_payout(group, "bob.near".parse().unwrap(), false, Ok(())));
+        assert!(escrow_ids[..2].iter().all(|escrow_id| contract.internal_get_deposit(escrow_id).is_none()));
+        assert_eq!(contract.get_stats().stats.total_claimed, 2);
+        assert_eq!(contract.get_stats().locked, vec![LockedValue { asset: token(), amount: U128(10) }]);
+    }
+
+    #[test]
+    fn refund_many_sends_one_transfer_per_sender() {
+        let (mut contract, escrow_ids) = indexed_contract();
+
+        at_stage("carol.near", Timelocks::DEFAULT.public_cancellation.into());
+        let mut ids = escrow_ids.clone();
+        ids.push("00".repeat(32));
+        let results = contract.refund_many(ids);
+
+        assert!(results[..3].iter().all(|result| result.success));
+        assert_eq!(results[3], BatchResult {
+            escrow_id: "00".repeat(32),
+            success: false,
+            error: Some("No hay depósito para ese escrow_id".to_string()),
+        });
+        assert!(escrow_ids.iter().all(|escrow_id| contract.internal_get_deposit(escrow_id).unwrap().refunded));
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts.len(), 2);
+        let args = function_call_args(&receipts[0]);
+        assert_eq!(args["receiver_id"], "alice.near");
+        assert_eq!(args["amount"], "30");
+    }
+
+    #[test]
+    fn refund_many_before_cancellation_fails_per_item() {
+        let (mut contract, escrow_ids) = indexed_contract();
+
+        at_stage("bob.near", 0);
+        let results = contract.refund_many(escrow_ids);
+
+        assert!(results.iter().all(|result| result.error.as_deref() == Some("El tiempo de espera aún no ha pasado")));
+        assert!(near_sdk::test_utils::get_created_receipts().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Demasiados elementos en el lote")]
+    fn claim_many_is_bounded() {
+        let (mut contract, escrow_ids) = indexed_contract();
+
+        contract.claim_many(vec![(escrow_ids[0].clone(), part_secret(0)); MAX_BATCH_SIZE + 1]);
+    }
+
+    /// `MAX_BATCH_SIZE` escrows NEP-141, cada uno con un recipient distinto y
+    /// el hashlock de `part_secret(i)`
+    fn full_batch_contract() -> Contract {
+        let mut contract = new_contract();
+        at_stage("bob.near", 0);
+        for i in 0..MAX_BATCH_SIZE as u64 {
+            let hash = hashlock(HashAlgorithm::Sha256, &part_secret(i));
+            let deposit = DepositInfo {
+                amount: U128(10),
+                recipient: format!("recipient{i}.near").parse().unwrap(),
+                ..ft_deposit_info("alice.near".parse().unwrap(), &hash)
+            };
+            contract.internal_save_deposit(&hash, &deposit);
+            contract.internal_lock(&deposit.asset, deposit.amount.0);
+        }
+        contract
+    }
+
+    fn full_batch() -> Vec<(String, String)> {
+        (0..MAX_BATCH_SIZE as u64)
+            .map(|i| (hashlock(HashAlgorithm::Sha256, &part_secret(i)), part_secret(i)))
+            .collect()
+    }
+
+    #[test]
+    fn claim_many_at_max_batch_size_fits_in_a_transaction() {
+        let mut contract = full_batch_contract();
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id("bob.near".parse().unwrap())
+            .prepaid_gas(Gas::from_tgas(300))
+            .build());
+        let results = contract.claim_many(full_batch());
+        assert!(results.iter().all(|result| result.success));
+
+        // Un ft_transfer y un callback por recipient. El gas usado ya incluye el
+        // que llevan adjunto y las tarifas de sus receipts
+        assert_eq!(near_sdk::test_utils::get_created_receipts().len(), 2 * MAX_BATCH_SIZE);
+        assert!(env::used_gas().as_gas() + GAS_RESERVED_FOR_BATCH.as_gas() <= Gas::from_tgas(300).as_gas());
+    }
+
+    #[test]
+    #[should_panic(expected = "No hay gas suficiente para los pagos del lote")]
+    fn claim_many_without_enough_gas_is_rejected() {
+        let mut contract = full_batch_contract();
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id("bob.near".parse().unwrap())
+            .prepaid_gas(Gas::from_tgas(100))
+            .build());
+        contract.claim_many(full_batch());
+    }
+
+    /// Depósitos del receptor de cada transferencia de NEAR de los receipts
+    fn near_transfers() -> Vec<(AccountId, u128)> {
+        near_sdk::test_utils::get_created_receipts()
+            .into_iter()
+            .filter_map(|receipt| match receipt.actions[0] {
+                near_sdk::mock::MockAction::Transfer { deposit, .. } => Some((receipt.receiver_id, deposit.as_yoctonear())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cleanup_refunds_expired_escrows_and_pays_a_bounty() {
+        let (mut contract, escrow_ids) = indexed_contract();
+        let storage_deposits: Vec<u128> =
+            escrow_ids.iter().map(|escrow_id| contract.internal_get_deposit(escrow_id).unwrap().storage_deposit.0).collect();
+        // Ningún secreto se reveló, así que el prepago del hashlock vuelve entero
+        let revealed_deposit = contract.internal_revealed_entry_cost();
+
+        at_stage("carol.near", Timelocks::DEFAULT.public_cancellation.into());
+        let result = contract.cleanup(None, 2);
+        assert_eq!(result, CleanupResult { refunded: escrow_ids[..2].to_vec(), removed: vec![], next_index: Some(U128(2)) });
+        assert!(!contract.internal_get_deposit(&escrow_ids[2]).unwrap().refunded);
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(function_call_args(&receipts[0])["amount"], "20");
+
+        let group: Vec<(String, DepositInfo)> = escrow_ids[..2]
+            .iter()
+            .map(|escrow_id| (escrow_id.clone(), DepositInfo { refunded: false, ..contract.internal_get_deposit(escrow_id).unwrap() }))
+            .collect();
+        testing_env!(VMContextBuilder::new().predecessor_account_id(env::current_account_id()).build());
+        assert!(contract.resolve_batch_payout(group, "carol.near".parse().unwrap(), true, Ok(())));
+
+        // El 10% del almacenamiento de cada escrow para carol.near y el resto para alice.near
+        let expected: Vec<(AccountId, u128)> = storage_deposits[..2]
+            .iter()
+            .flat_map(|storage_deposit| {
+                [
+                    ("alice.near".parse().unwrap(), storage_deposit - storage_deposit / 10 + revealed_deposit),
+                    ("carol.near".parse().unwrap(), storage_deposit / 10),
+                ]
+            })
+            .collect();
+        assert_eq!(near_transfers(), expected);
+        assert_eq!(events()[0]["event"], "escrow_cleanup");
+        assert_eq!(events()[0]["data"][0]["bounty"], (storage_deposits[0] / 10).to_string());
+        assert_eq!(contract.deposits.len(), 1);
+        assert_eq!(contract.get_stats().stats.total_refunded, 2);
+    }
+
+    #[test]
+    fn cleanup_skips_escrows_that_are_not_expired() {
+        let (mut contract, _) = indexed_contract();
+
+        at_stage("carol.near", Timelocks::DEFAULT.cancellation.into());
+        let cancellation = Timelocks { public_cancellation: Timelocks::DEFAULT.cancellation + 60, ..Timelocks::DEFAULT };
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        contract.internal_save_deposit(&hash, &DepositInfo { timelocks: cancellation, timestamp: 0, ..ft_deposit_info("alice.near".parse().unwrap(), &hash) });
+
+        // Los tres de indexed_contract están en cancelación pública; éste sólo en la exclusiva del taker
+        at_stage("carol.near", Timelocks::DEFAULT.public_cancellation.into());
+        let result = contract.cleanup(None, MAX_BATCH_SIZE as u32);
+        assert_eq!(result.refunded.len(), 3);
+        assert!(!result.refunded.contains(&hash));
+        assert_eq!(result.next_index, None);
+        assert_eq!(contract.cleanup(None, MAX_BATCH_SIZE as u32), CleanupResult::default());
+    }
+
+    #[test]
+    fn cleanup_visits_at_most_limit_entries_from_the_cursor() {
+        let (mut contract, escrow_ids) = indexed_contract();
+
+        // Las entradas que se miran cuentan aunque no se traten
+        at_stage("carol.near", 0);
+        let result = contract.cleanup(None, 2);
+        assert_eq!(result, CleanupResult { next_index: Some(U128(2)), ..CleanupResult::default() });
+
+        at_stage("carol.near", Timelocks::DEFAULT.public_cancellation.into());
+        let result = contract.cleanup(Some(U128(1)), 1);
+        assert_eq!(result, CleanupResult { refunded: vec![escrow_ids[1].clone()], removed: vec![], next_index: Some(U128(2)) });
+
+        let result = contract.cleanup(result.next_index, 5);
+        assert_eq!(result, CleanupResult { refunded: vec![escrow_ids[2].clone()], removed: vec![], next_index: None });
+        assert_eq!(contract.cleanup(Some(U128(7)), 1), CleanupResult::default());
+    }
+
+    #[test]
+    fn cleanup_removes_abandoned_claimed_entries() {
+        let mut contract = new_contract();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        at_stage("alice.near", 0);
+        let deposit = DepositInfo {
+            claimed: true,
+            storage_deposit: U128(1_000),
+            ..fee_deposit(&hash)
+        };
+        contract.internal_save_deposit(&hash, &deposit);
+        contract.internal_lock(&deposit.asset, deposit.amount.0);
+
+        // Puede haber un pago en curso hasta que pasa el margen
+        let cancellation = u64::from(Timelocks::DEFAULT.cancellation);
+        at_stage("carol.near", cancellation);
+        assert_eq!(contract.cleanup(None, 1), CleanupResult::default());
+
+        at_stage("carol.near", cancellation + SETTLED_GRACE_PERIOD);
+        assert_eq!(contract.cleanup(None, 1), CleanupResult { removed: vec![hash.clone()], ..CleanupResult::default() });
+        assert!(contract.internal_get_deposit(&hash).is_none());
+        assert_eq!(near_transfers(), vec![
+            ("alice.near".parse().unwrap(), 900),
+            ("carol.near".parse().unwrap(), 100),
+        ]);
+
+        // Se resuelve como un pago confirmado
+        assert!(contract.get_stats().locked.is_empty());
+        assert_eq!(contract.get_stats().stats.total_claimed, 1);
+        assert_eq!(contract.get_protocol_fees(), fee_balance(30));
+    }
+
+    #[test]
+    #[should_panic(expected = "El límite debe estar entre 1 y el tamaño máximo del lote")]
+    fn cleanup_limit_must_be_positive() {
+        let mut contract = new_contract();
+        contract.cleanup(None, 0);
+    }
+
+    #[test]
+    fn revealed_secret_cannot_back_a_new_escrow() {
+        let (mut contract, escrow_ids) = indexed_contract();
+        let alice: AccountId = "alice.near".parse().unwrap();
+
+        at_stage("bob.near", 1);
+        contract.claim_tokens(escrow_ids[0].clone(), part_secret(0));
+
+        // Sólo se guarda el hash con el algoritmo del escrow
+        let sha256 = hashlock(HashAlgorithm::Sha256, &part_secret(0));
+        assert_eq!(contract.get_hashlock_revealed_at(sha256.to_uppercase()), Some(U64(1_000_000_000)));
+        assert!(contract.get_hashlock_revealed_at(hashlock(HashAlgorithm::Keccak256, &part_secret(0))).is_none());
+
+        // Otra orden con el mismo hashlock
+        from_token();
+        let msg = EscrowMsgV1 { order_hash: "cd".repeat(32), ..escrow_msg(&sha256, HashAlgorithm::Sha256) };
+        assert_eq!(unused(contract.ft_on_transfer(alice.clone(), U128(10), msg_json(&msg))), U128(10));
+        assert_eq!(contract.deposits.len(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "El secreto de ese hashlock ya se reveló en otro escrow")]
+    fn revealed_hashlock_is_rejected_for_near_escrows() {
+        let (mut contract, escrow_id) = near_escrow_contract();
+
+        at_stage("bob.near", 0);
+        contract.claim_tokens(escrow_id, SECRET.to_string());
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id("alice.near".parse().unwrap())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        let msg = EscrowMsgV1 { order_hash: "cd".repeat(32), ..escrow_msg(&hashlock(HashAlgorithm::Sha256, SECRET), HashAlgorithm::Sha256) };
+        contract.recive_near(EscrowMsg::V1(msg));
+    }
+
+    #[test]
+    fn partial_reveal_blocks_part_and_root() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+        let root = contract.internal_get_deposit(&escrow_id).unwrap().hashlock;
+
+        contract.claim_partial(escrow_id, part_secret(0), 0, proofs[0].clone(), U128(25));
+
+        assert!(contract.get_hashlock_revealed_at(root).is_some());
+        assert!(contract.get_hashlock_revealed_at(hashlock(HashAlgorithm::Sha256, &part_secret(0))).is_some());
+        assert!(contract.get_hashlock_revealed_at(hashlock(HashAlgorithm::Sha256, &part_secret(1))).is_none());
+    }
+
+    #[test]
+    fn revealed_hashes_are_paid_when_the_escrow_is_created() {
+        let (mut contract, escrow_id, proofs) = partial_contract();
+        let deposit = contract.internal_get_deposit(&escrow_id).unwrap();
+
+        // La raíz y los cinco secretos de las cuatro partes
+        assert_eq!(deposit.revealed_entries(), 6);
+        let entry_cost = contract.internal_revealed_entry_cost();
+        assert_eq!(deposit.revealed_deposit, U128(entry_cost * 6));
+        let liabilities = contract.near_liabilities;
+
+        // Cada hash guardado gasta una entrada del prepago, que deja de deberse
+        let initial_storage = env::storage_usage();
+        for (index, fill) in [(0, 25), (1, 50), (2, 75), (4, 100)] {
+            contract.claim_partial(escrow_id.clone(), part_secret(index), index, proofs[index as usize].clone(), U128(fill));
+        }
+        assert_eq!(storage_cost_since(initial_storage), entry_cost * 5);
+        assert_eq!(contract.internal_get_deposit(&escrow_id).unwrap().revealed_deposit, U128(entry_cost));
+        assert_eq!(contract.near_liabilities, liabilities - entry_cost * 5);
+    }
+
+    #[test]
+    fn failed_claim_does_not_record_the_secret() {
+        let (mut contract, escrow_ids) = indexed_contract();
+
+        at_stage("bob.near", 0);
+        contract.claim_many(vec![(escrow_ids[0].clone(), part_secret(1))]);
+
+        assert!(contract.get_hashlock_revealed_at(hashlock(HashAlgorithm::Sha256, &part_secret(1))).is_none());
+    }
+
+    #[test]
+    fn views_return_cross_chain_order_data() {
+        let (contract, escrow_ids) = indexed_contract();
+
+        let view = near_sdk::serde_json::to_value(contract.get_deposit_info(escrow_ids[0].clone()).unwrap()).unwrap();
+        assert_eq!(view["src_chain_id"], 397);
+        assert_eq!(view["dst_chain_id"], 1);
+        assert_eq!(view["evm_maker"], format!("0x{}", "11".repeat(20)));
+        assert_eq!(view["dst_token"], format!("0x{}", "00".repeat(20)));
+        assert_eq!(view["dst_amount"], "1000");
+
+        let page = near_sdk::serde_json::to_value(contract.get_deposits(None, Some(1), None)).unwrap();
+        assert_eq!(page["deposits"][0]["evm_maker"], view["evm_maker"]);
+    }
+
+    #[test]
+    fn invalid_cross_chain_data_is_refunded() {
+        let mut contract = new_contract();
+        let alice: AccountId = "alice.near".parse().unwrap();
+        fund_near(&mut contract, &alice);
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+
+        let same_chain = EscrowMsgV1 { dst_chain_id: ChainId::NEAR, ..escrow_msg(&hash, HashAlgorithm::Sha256) };
+        assert_eq!(unused(contract.ft_on_transfer(alice.clone(), U128(10), msg_json(&same_chain))), U128(10));
+
+        // Dirección de 19 bytes y cadena 0
+        let mut msg = near_sdk::serde_json::to_value(EscrowMsg::V1(escrow_msg(&hash, HashAlgorithm::Sha256))).unwrap();
+        msg["evm_maker"] = format!("0x{}", "11".repeat(19)).into();
+        assert_eq!(unused(contract.ft_on_transfer(alice.clone(), U128(10), msg.to_string())), U128(10));
+        msg["evm_maker"] = format!("0x{}", "11".repeat(20)).into();
+        msg["dst_chain_id"] = 0.into();
+        assert_eq!(unused(contract.ft_on_transfer(alice, U128(10), msg.to_string())), U128(10));
+
+        assert_eq!(contract.deposits.len(), 0);
+    }
+
+    const MAKER_SEED: [u8; 32] = [7; 32];
+
+    fn maker() -> AccountId {
+        "maker.near".parse().unwrap()
+    }
+
+    fn signed_order(hash: &str) -> Order {
+        Order {
+            verifying_contract: env::current_account_id(),
+            maker: maker(),
+            nonce: 5,
+            epoch: 0,
+            expiration: 1_000,
+            token: "token.near".parse().unwrap(),
+            amount: U128(10),
+            hashlock: hash.to_string(),
+            hash_algorithm: HashAlgorithm::Sha256,
+            timelocks: None,
+            safety_deposit: U128(500),
+            src_chain_id: ChainId::NEAR,
+            dst_chain_id: ChainId::new(1).unwrap(),
+            evm_maker: EVM_MAKER,
+            dst_token: EvmAddress::ZERO,
+            dst_amount: U128(1000),
+            auction: None,
+        }
+    }
+
+    fn sign(order: &Order, seed: [u8; 32]) -> Base64VecU8 {
+        use ed25519_dalek::Signer;
+        let key = ed25519_dalek::SigningKey::from_bytes(&seed);
+        Base64VecU8(key.sign(&order.signed_bytes()).to_bytes().to_vec())
+    }
+
+    fn order_public_key(seed: [u8; 32]) -> PublicKey {
+        let key = ed25519_dalek::SigningKey::from_bytes(&seed);
+        PublicKey::from_parts(CurveType::ED25519, key.verifying_key().to_bytes().to_vec()).unwrap()
+    }
+
+    /// El maker con clave registrada, saldo de NEAR y 100 tokens depositados.
+    /// Deja el contexto de resolver.near
+    fn order_contract() -> Contract {
+        let mut contract = new_contract();
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(maker())
+            .attached_deposit(NearToken::from_millinear(10))
+            .build());
+        contract.set_order_key(Some(order_public_key(MAKER_SEED)));
+        fund_near(&mut contract, &maker());
+        assert_eq!(unused(contract.ft_on_transfer(maker(), U128(100), String::new())), U128(0));
+        resolver();
+        contract
+    }
+
+    /// Contexto de resolver.near con el depósito de seguridad de `signed_order` adjunto
+    fn resolver() {
+        resolver_at(0);
+    }
+
+    #[test]
+    fn fill_order_creates_escrow_for_maker() {
+        let mut contract = order_contract();
+        let hash = hashlock(HashAlgorithm::Sha256, SECRET);
+        let order = signed_order(&hash);
+        let near_balance = contract.get_near_balance(maker()).0;
+        let liabilities = contract.near_liabilities;
+
+        let escrow_id = contract.fill_order(order.clone(), sign(&order, MAKER_SEED), None);
+
+        let deposit = contract.internal_get_deposit(&escrow_id).unwrap();
+        assert_eq!(deposit.sender, maker());
+        assert_eq!(deposit.taker, "resolver.near".parse::<AccountId>().unwrap());
+        assert_eq!(deposit.amount, U128(10));
+        assert_eq!(deposit.order_hash, order.hash());
+        assert_eq!(deposit.safety_deposit, U128(500));
+        // El depósito de seguridad lo adjunta el resolver; el maker sólo paga el almacenamiento
+        assert_eq!(deposit.safety_deposit_payer, Some("resolver.near".parse().unwrap()));
+        let maker_paid = deposit.storage_deposit.0 + deposit.revealed_deposit.0;
+        assert_eq!(contract.get_near_balance(maker()).0, near_balance - maker_paid);
+        assert_eq!(contract.near_liabilities, liabilities - deposit.storage_deposit.0 + 500);
+        assert_eq!(contract.get_ft_balance(maker(), "token.near".parse().unwrap()), U128(90));
+        assert!(contract.is_nonce_used(maker(), 5));
+        assert!(!contract.is_nonce_used(maker(), 6));
+        assert_eq!(contract.get_order_hash(order.clone()), order.hash());
+
+        let event = events().pop().unwrap();
+        assert_eq!(event["event"], "order_filled");
+        assert_eq!(event["data"][0]["escrow_id"], escrow_id);
+        assert_eq!(event["data"][0]["nonce"], 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "Hay que adjuntar el depósito de seguridad de la orden")]
+    fn fill_order_requires_the_safety_deposit() {
+        let mut contract = order_contract();
+        let order = signed_order(&hashlock(HashAlgorithm::Sha256, SECRET));
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id("resolver.near".parse().unwrap())
+            .attached_deposit(NearToken::from_yoctonear(499))
+            .build());
+        contract.fill_order(order.clone(), sign(&order, MAKER_SEED), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "La firma de la orden no es válida")]
+    fn fill_order_rejects_tampered_order() {
+        let mut contract = order_contract();
+        let order = signed_order(&hashlock(HashAlgorithm::Sha256, SECRET));
+        let signature = sign(&order, MAKER_SEED);
+        contract.fill_order(Order { dst_amount: U128(1), ..order }, signature, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "La firma de la orden no es válida")]
+    fn fill_order_rejects_other_key() {
+        let mut contract = order_contract();
+        let order = signed_order(&hashlock(HashAlgorithm::Sha256, SECRET));
+        contract.fill_order(order.clone(), sign(&order, [8; 32]), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "La firma debe ser de 64 bytes")]
+    fn fill_order_rejects_short_signature() {
+        let mut contract = order_contract();
+        let order = signed_order(&hashlock(HashAlgorithm::Sha256, SECRET));
+        contract.fill_order(order, Base64VecU8(vec![0; 63]), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "El maker no tiene clave para firmar órdenes")]
+    fn fill_order_requires_registered_key() {
+        let mut contract = order_contract();
+        testing_env!(VMContextBuilder::new().predecessor_account_id(maker()).attached_deposit(ONE_YOCTO).build());
+        contract.set_order_key(None);
+        assert!(contract.get_order_key(maker()).is_none());
+
+        resolver();
+        let order = signed_order(&hashlock(HashAlgorithm::Sha256, SECRET));
+        contract.fill_order(order.clone(), sign(&order, MAKER_SEED), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "La orden ha caducado")]
+    fn fill_order_rejects_expired_order() {
+        let mut contract = order_contract();
+        let order = signed_order(&hashlock(HashAlgorithm::Sha256, SECRET));
+        resolver_at(1_000);
+        contract.fill_order(order.clone(), sign(&order, MAKER_SEED), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "La orden es de otro contrato")]
+    fn fill_order_rejects_other_contract() {
+        let mut contract = order_contract();
+        let order = Order {
+            verifying_contract: "other.near".parse().unwrap(),
+            ..signed_order(&hashlock(HashAlgorithm::Sha256, SECRET))
+        };
+        contract.fill_order(order.clone(), sign(&order, MAKER_SEED), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "El nonce de la orden ya se usó")]
+    fn fill_order_rejects_reused_nonce() {
+        let mut contract = order_contract();
+        let order = signed_order(&hashlock(HashAlgorithm::Sha256, SECRET));
+        contract.fill_order(order.clone(), sign(&order, MAKER_SEED), None);
+
+        // Otra orden con el mismo nonce
+        let other = Order { hashlock: hashlock(HashAlgorithm::Sha256, &"11".repeat(32)), ..order };
+        contract.fill_order(other.clone(), sign(&other, MAKER_SEED), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Saldo de tokens insuficiente")]
+    fn fill_order_requires_maker_balance() {
+        let mut contract = order_contract();
+        let order = Order { amount: U128(101), ..signed_order(&hashlock(HashAlgorithm::Sha256, SECRET)) };
+        contract.fill_order(order.clone(), sign(&order, MAKER_SEED), None);
+    }
+
+    #[test]
+    fn set_order_key_refunds_excess() {
+        let mut contract = new_contract();
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(maker())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.set_order_key(Some(order_public_key(MAKER_SEED)));
+        assert_eq!(contract.get_order_key(maker()), Some(order_public_key(MAKER_SEED)));
+
+        let refund = near_sdk::test_utils::get_created_receipts()
+            .iter()
+            .flat_map(|receipt| receipt.actions.clone())
+            .find_map(|action| match action {
+                near_sdk::mock::MockAction::Transfer { deposit, .. } => Some(deposit),
+                _ => None,
+            })
+            .unwrap();
+        assert!(refund < NearToken::from_near(1) && refund > NearToken::from_millinear(990));
+    }
+
+    #[test]
+    fn removing_the_order_key_refunds_its_storage() {
+        let mut contract = new_contract();
+        maker_with_deposit();
+        let initial_storage = env::storage_usage();
+        contract.set_order_key(Some(order_public_key(MAKER_SEED)));
+        let storage = env::storage_usage();
+        let key_cost = u128::from(storage - initial_storage) * env::storage_byte_cost().as_yoctonear();
+        assert!(key_cost > 0);
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(maker())
+            .attached_deposit(ONE_YOCTO)
+            .storage_usage(storage)
+            .build());
+        contract.set_order_key(None);
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, maker());
+        assert!(matches!(
+            receipts[0].actions[0],
+            near_sdk::mock::MockAction::Transfer { deposit, .. } if deposit.as_yoctonear() == key_cost + 1
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "La clave de las órdenes debe ser ed25519")]
+    fn set_order_key_rejects_other_curves() {
+        let mut contract = new_contract();
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(maker())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.set_order_key(Some(PublicKey::from_parts(CurveType::SECP256K1, vec![1; 64]).unwrap()));
+    }
+
+    #[test]
+    fn ft_balance_deposit_and_withdrawal() {
+        let mut contract = new_contract();
+        let token_id: AccountId = "token.near".parse().unwrap();
+
+        // Sin saldo de NEAR para el almacenamiento se devuelven los tokens
+        from_token();
+        assert_eq!(unused(contract.ft_on_transfer(maker(), U128(100), String::new())), U128(100));
+
+        fund_near(&mut contract, &maker());
+        let near_balance = contract.get_near_balance(maker()).0;
+        assert_eq!(unused(contract.ft_on_transfer(maker(), U128(100), String::new())), U128(0));
+        assert_eq!(unused(contract.ft_on_transfer(maker(), U128(20), String::new())), U128(0));
+        assert_eq!(contract.get_ft_balance(maker(), token_id.clone()), U128(120));
+        assert!(contract.get_near_balance(maker()).0 < near_balance);
+        assert_eq!(contract.internal_held(&token()), 120);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(maker()).attached_deposit(ONE_YOCTO).build());
+        contract.withdraw_ft(token_id.clone(), U128(50));
+        assert_eq!(contract.get_ft_balance(maker(), token_id.clone()), U128(70));
+
+        // Si la transferencia falla el saldo vuelve
+        testing_env!(VMContextBuilder::new().predecessor_account_id(env::current_account_id()).build());
+        assert!(!contract.resolve_ft_withdrawal(maker(), token_id.clone(), U128(50), Err(PromiseError::Failed)));
+        assert_eq!(contract.get_ft_balance(maker(), token_id.clone()), U128(120));
+        assert_eq!(contract.internal_held(&token()), 120);
+    }
+
+    /// Orden con subasta de 2000 a 1000 entre los segundos 100 y 200
+    fn auction_order() -> Order {
+        Order {
+            auction: Some(AuctionDetails { start_time: 100, duration: 100, start_amount: U128(2000), points: vec![] }),
+            ..signed_order(&hashlock(HashAlgorithm::Sha256, SECRET))
+        }
+    }
+
+    fn resolver_at(seconds: u64) {
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id("resolver.near".parse().unwrap())
+            .attached_deposit(NearToken::from_yoctonear(500))
+            .block_timestamp(seconds * NANOS_PER_SECOND)
+            .build());
+    }
+
+    #[test]
+    fn fill_order_uses_current_auction_amount() {
+        let mut contract = order_contract();
+        let order = auction_order();
+
+        resolver_at(150);
+        assert_eq!(contract.get_order_dst_amount(order.clone()), U128(1500));
+        let escrow_id = contract.fill_order(order.clone(), sign(&order, MAKER_SEED), None);
+        assert_eq!(contract.internal_get_deposit(&escrow_id).unwrap().dst_amount, U128(1500));
+
+        // Un taker puede ofrecer más que la subasta
+        let second = Order { nonce: 6, hashlock: hashlock(HashAlgorithm::Sha256, &"11".repeat(32)), ..order };
+        resolver_at(250);
+        assert_eq!(contract.get_order_dst_amount(second.clone()), U128(1000));
+        let escrow_id = contract.fill_order(second.clone(), sign(&second, MAKER_SEED), Some(U128(1200)));
+        assert_eq!(contract.internal_get_deposit(&escrow_id).unwrap().dst_amount, U128(1200));
+    }
+
+    #[test]
+    #[should_panic(expected = "El importe ofrecido está por debajo de la subasta")]
+    fn fill_order_rejects_amount_below_auction() {
+        let mut contract = order_contract();
+        let order = auction_order();
+        resolver_at(150);
+        contract.fill_order(order.clone(), sign(&order, MAKER_SEED), Some(U128(1499)));
+    }
+
+    #[test]
+    #[should_panic(expected = "El importe de la subasta no puede subir")]
+    fn fill_order_rejects_invalid_auction() {
+        let mut contract = order_contract();
+        let order = Order { dst_amount: U128(3000), ..auction_order() };
+        contract.fill_order(order.clone(), sign(&order, MAKER_SEED), None);
+    }
+
+    /// Contexto del maker con 1 NEAR adjunto
+    fn maker_with_deposit() {
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(maker())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+    }
+
+    #[test]
+    fn order_views_report_why_an_order_is_not_fillable() {
+        let mut contract = order_contract();
+        let order = signed_order(&hashlock(HashAlgorithm::Sha256, SECRET));
+        let signature = sign(&order, MAKER_SEED);
+
+        assert!(contract.is_order_fillable(order.clone(), signature.clone()));
+        assert_eq!(contract.get_order_error(order.clone(), signature.clone()), None);
+        assert_eq!(
+            contract.get_order_error(order.clone(), sign(&order, [8; 32])).as_deref(),
+            Some("La firma de la orden no es válida")
+        );
+        let too_big = Order { amount: U128(101), ..order.clone() };
+        assert_eq!(
+            contract.get_order_error(too_big.clone(), sign(&too_big, MAKER_SEED)).as_deref(),
+            Some("Saldo de tokens insuficiente")
+        );
+
+        contract.fill_order(order.clone(), signature.clone(), None);
+        assert!(!contract.is_order_fillable(order.clone(), signature.clone()));
+        assert_eq!(contract.get_order_error(order, signature).as_deref(), Some("El nonce de la orden ya se usó"));
+    }
+
+    #[test]
+    fn cancel_order_blocks_fills() {
+        let mut contract = order_contract();
+        let order = signed_order(&hashlock(HashAlgorithm::Sha256, SECRET));
+        let signature = sign(&order, MAKER_SEED);
+
+        maker_with_deposit();
+        contract.cancel_order(order.hash().to_uppercase());
+        let event = events().pop().unwrap();
+        assert_eq!(event["event"], "order_cancelled");
+        assert_eq!(event["data"][0]["order_hash"], order.hash());
+
+        assert!(contract.is_order_cancelled(maker(), order.hash()));
+        assert!(!contract.is_order_cancelled("other.near".parse().unwrap(), order.hash()));
+        assert_eq!(contract.get_order_error(order, signature).as_deref(), Some("La orden está cancelada"));
+    }
+
+    #[test]
+    #[should_panic(expected = "La orden está cancelada")]
+    fn fill_cancelled_order() {
+        let mut contract = order_contract();
+        let order = signed_order(&hashlock(HashAlgorithm::Sha256, SECRET));
+        maker_with_deposit();
+        contract.cancel_order(order.hash());
+
+        resolver();
+        contract.fill_order(order.clone(), sign(&order, MAKER_SEED), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "La orden ya está cancelada")]
+    fn cancel_order_twice() {
+        let mut contract = order_contract();
+        let order_hash = signed_order(&hashlock(HashAlgorithm::Sha256, SECRET)).hash();
+        maker_with_deposit();
+        contract.cancel_order(order_hash.clone());
+        contract.cancel_order(order_hash);
+    }
+
+    #[test]
+    fn invalidate_nonces_marks_the_bitmap() {
+        let mut contract = order_contract();
+        maker_with_deposit();
+        contract.invalidate_nonces(0, U128(1 << 5 | 1 << 7));
+        contract.invalidate_nonces(0, U128(1));
+        contract.invalidate_nonces(1, U128(1 << 127));
+
+        for nonce in [0, 5, 7, 255] {
+            assert!(contract.is_nonce_used(maker(), nonce), "{nonce}");
+        }
+        for nonce in [1, 6, 128, 254] {
+            assert!(!contract.is_nonce_used(maker(), nonce), "{nonce}");
+        }
+        let event = events().pop().unwrap();
+        assert_eq!(event["event"], "nonces_invalidated");
+        assert_eq!(event["data"][0]["series"], 1);
+        assert_eq!(event["data"][0]["bitmap"], (1u128 << 127).to_string());
+
+        let order = signed_order(&hashlock(HashAlgorithm::Sha256, SECRET));
+        assert_eq!(
+            contract.get_order_error(order.clone(), sign(&order, MAKER_SEED)).as_deref(),
+            Some("El nonce de la orden ya se usó")
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "El bitmap no invalida ningún nonce")]
+    fn invalidate_nonces_requires_bits() {
+        let mut contract = order_contract();
+        maker_with_deposit();
+        contract.invalidate_nonces(0, U128(0));
+    }
+
+    #[test]
+    fn increase_epoch_invalidates_older_orders() {
+        let mut contract = order_contract();
+        let old = signed_order(&hashlock(HashAlgorithm::Sha256, SECRET));
+
+        maker_with_deposit();
+        assert_eq!(contract.increase_epoch(), 1);
+        assert_eq!(contract.get_epoch(maker()), 1);
+        assert_eq!(events().pop().unwrap()["data"][0]["epoch"], 1);
+        assert_eq!(
+            contract.get_order_error(old.clone(), sign(&old, MAKER_SEED)).as_deref(),
+            Some("La orden es de otra época del maker")
+        );
+
+        resolver();
+        let current = Order { epoch: 1, ..old };
+        contract.fill_order(current.clone(), sign(&current, MAKER_SEED), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "La orden es de otra época del maker")]
+    fn fill_order_from_previous_epoch() {
+        let mut contract = order_contract();
+        maker_with_deposit();
+        contract.increase_epoch();
+
+        resolver();
+        let order = signed_order(&hashlock(HashAlgorithm::Sha256, SECRET));
+        contract.fill_order(order.clone(), sign(&order, MAKER_SEED), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Saldo de tokens insuficiente")]
+    fn withdraw_ft_above_balance() {
+        let mut contract = order_contract();
+        testing_env!(VMContextBuilder::new().predecessor_account_id(maker()).attached_deposit(ONE_YOCTO).build());
+        contract.withdraw_ft("token.near".parse().unwrap(), U128(101));
+    }
+
+    //TODO: hacer el test del flow del contrato
+}
@@ -0,0 +1,86 @@
+use near_sdk::env;
+
+/// Hoja del árbol de secretos de una orden con rellenos parciales, igual que en
+/// los escrows de Fusion+: `keccak256(uint64(index) ++ hash(secreto))`.
+pub fn leaf(index: u64, secret_hash: &[u8]) -> Vec<u8> {
+    let mut data = index.to_be_bytes().to_vec();
+    data.extend_from_slice(secret_hash);
+    env::keccak256(&data)
+}
+
+/// Recalcula la raíz a partir de una hoja y su prueba (pares ordenados, como
+/// `MerkleProof` de OpenZeppelin).
+pub fn process_proof(leaf: Vec<u8>, proof: &[Vec<u8>]) -> Vec<u8> {
+    proof.iter().fold(leaf, |node, sibling| hash_pair(&node, sibling))
+}
+
+pub fn hash_pair(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    env::keccak256(&[first, second].concat())
+}
+
+/// Índice del secreto que debe revelarse para llevar el total rellenado de
+/// `filled` a `cumulative` en una orden de `total` dividida en `parts` partes.
+/// Hay `parts + 1` secretos: el último sólo sirve para completar la orden.
+///
+/// Devuelve `None` si el relleno no avanza, se pasa del total o reutilizaría
+/// el índice del relleno anterior.
+pub fn expected_index(total: u128, filled: u128, cumulative: u128, parts: u16) -> Option<u64> {
+    if cumulative <= filled || cumulative > total {
+        return None;
+    }
+
+    let index_of = |amount: u128| (amount - 1).checked_mul(u128::from(parts)).map(|value| value / total);
+
+    let index = index_of(cumulative)?;
+    if cumulative == total {
+        return u64::try_from(index + 1).ok();
+    }
+    if filled > 0 && index_of(filled)? == index {
+        return None;
+    }
+    u64::try_from(index).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expected_index_first_fills() {
+        // 4 partes de 25: cada tramo tiene su propio secreto
+        assert_eq!(expected_index(100, 0, 1, 4), Some(0));
+        assert_eq!(expected_index(100, 0, 25, 4), Some(0));
+        assert_eq!(expected_index(100, 0, 26, 4), Some(1));
+        assert_eq!(expected_index(100, 0, 99, 4), Some(3));
+    }
+
+    #[test]
+    fn expected_index_full_fill_uses_last_secret() {
+        assert_eq!(expected_index(100, 0, 100, 4), Some(4));
+        assert_eq!(expected_index(100, 80, 100, 4), Some(4));
+    }
+
+    #[test]
+    fn expected_index_rejects_same_part_twice() {
+        assert_eq!(expected_index(100, 10, 20, 4), None);
+        assert_eq!(expected_index(100, 10, 30, 4), Some(1));
+    }
+
+    #[test]
+    fn expected_index_rejects_overlapping_fills() {
+        assert_eq!(expected_index(100, 50, 50, 4), None);
+        assert_eq!(expected_index(100, 50, 40, 4), None);
+        assert_eq!(expected_index(100, 50, 101, 4), None);
+    }
+
+    #[test]
+    fn proof_of_two_leaves() {
+        let a = leaf(0, &[1; 32]);
+        let b = leaf(1, &[2; 32]);
+        let root = hash_pair(&a, &b);
+
+        assert_eq!(process_proof(a.clone(), &[b.clone()]), root);
+        assert_eq!(process_proof(b, &[a]), root);
+    }
+}
@@ -0,0 +1,188 @@
+//! Versiones anteriores del estado y su conversión a la actual.
+//!
+//! El contrato original guardaba `DepositInfoV1` sin etiqueta de versión en un
+//! `UnorderedMap::new(0)`. Desde V2 cada depósito se guarda como
+//! `VersionedDepositInfo`. `V2` es el primer layout publicado desde entonces;
+//! un cambio de `DepositInfo` después de publicarlo congela el actual en un
+//! struct `DepositInfoV2` y añade una variante nueva con su conversión.
+//!
+//! El estado (`Contract`) lleva su versión en `STATE_VERSION_KEY`, que escriben
+//! `init` y `migrate`; el contrato original no la escribía y su ausencia es V1.
+//! `migrate` lee el layout que dice la etiqueta, nunca lo adivina. Antes de
+//! cambiar un campo de `Contract` se congela el layout publicado en un struct
+//! `ContractVn`, se sube `STATE_VERSION` y se añade su conversión.
+
+use near_sdk::borsh::{self, BorshDeserialize};
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::U128;
+use near_sdk::{env, near, require, AccountId};
+use std::collections::HashMap;
+
+use crate::asset::Asset;
+use crate::evm::{ChainId, EvmAddress};
+use crate::hashlock::{normalize_bytes32, HashAlgorithm};
+use crate::timelocks::Timelocks;
+use crate::{Contract, DepositInfo};
+
+/// Clave en la que near-sdk guarda el estado del contrato.
+const STATE_KEY: &[u8] = b"STATE";
+
+/// Clave con la versión del layout de `Contract`.
+const STATE_VERSION_KEY: &[u8] = b"STATE_VERSION";
+
+/// Versión del layout actual de `Contract`.
+pub const STATE_VERSION: u8 = 2;
+
+/// Depósito tal y como se guarda en el mapa de depósitos.
+#[near(serializers = [borsh])]
+#[derive(Clone)]
+pub enum VersionedDepositInfo {
+    V2(DepositInfo),
+}
+
+impl From<VersionedDepositInfo> for DepositInfo {
+    fn from(deposit: VersionedDepositInfo) -> Self {
+        match deposit {
+            VersionedDepositInfo::V2(deposit) => deposit,
+        }
+    }
+}
+
+/// Los depósitos siempre se guardan con la última variante.
+impl From<DepositInfo> for VersionedDepositInfo {
+    fn from(deposit: DepositInfo) -> Self {
+        VersionedDepositInfo::V2(deposit)
+    }
+}
+
+/// `DepositInfo` del contrato original.
+#[near(serializers = [borsh])]
+#[derive(Clone)]
+pub struct DepositInfoV1 {
+    pub sender: AccountId,
+    pub amount: U128,
+    pub timestamp: u64,
+    pub claimed: bool,
+}
+
+/// Estado del contrato original.
+#[near(serializers = [borsh])]
+pub struct ContractV1 {
+    pub deposits: UnorderedMap<String, DepositInfoV1>,
+    pub deposit_number: U128,
+}
+
+/// Versiones del estado que `migrate` sabe leer.
+pub enum VersionedContract {
+    V1(ContractV1),
+    V2(Box<Contract>),
+}
+
+impl VersionedContract {
+    /// Lee el estado con el layout de su etiqueta de versión. Si los bytes no
+    /// encajan exactamente con ese layout se entra en pánico.
+    pub fn read() -> Self {
+        let state = env::storage_read(STATE_KEY).unwrap_or_else(|| env::panic_str("El contrato no está inicializado"));
+
+        match read_state_version() {
+            None => VersionedContract::V1(decode(&state)),
+            Some(STATE_VERSION) => VersionedContract::V2(Box::new(decode(&state))),
+            Some(_) => env::panic_str("Versión del estado desconocida"),
+        }
+    }
+}
+
+/// Versión guardada del estado; `None` en el contrato original.
+pub fn read_state_version() -> Option<u8> {
+    env::storage_read(STATE_VERSION_KEY).map(|version| match version.as_slice() {
+        [version] => *version,
+        _ => env::panic_str("Versión del estado desconocida"),
+    })
+}
+
+/// Marca el estado con el layout actual.
+pub fn write_state_version() {
+    env::storage_write(STATE_VERSION_KEY, &[STATE_VERSION]);
+}
+
+fn decode<T: BorshDeserialize>(state: &[u8]) -> T {
+    borsh::from_slice(state).unwrap_or_else(|_| env::panic_str("El estado no corresponde con su versión"))
+}
+
+impl DepositInfoV1 {
+    /// En V1 la clave era el hash del secreto, se reclamaba en cualquier momento
+    /// y se devolvía pasadas 24 horas (`Timelocks::DEFAULT`). V1 no guardaba el
+    /// activo, así que se recibe aparte. No había taker, así que el sender ocupa
+    /// su lugar. Tampoco había otra cadena: las dos son NEAR y los datos de EVM
+    /// quedan a cero.
+    pub fn upgrade(self, key: &str, asset: Asset) -> DepositInfo {
+        DepositInfo {
+            hashlock: normalize_bytes32(key).unwrap_or_else(|| key.to_string()),
+            hash_algorithm: HashAlgorithm::Sha256,
+            asset,
+            taker: self.sender.clone(),
+            recipient: self.sender.clone(),
+            timelocks: Timelocks::DEFAULT,
+            order_hash: "00".repeat(32),
+            src_chain_id: ChainId::NEAR,
+            dst_chain_id: ChainId::NEAR,
+            evm_maker: EvmAddress::ZERO,
+            dst_token: EvmAddress::ZERO,
+            dst_amount: U128(0),
+            safety_deposit: U128(0),
+            safety_deposit_payer: None,
+            parts: None,
+            filled: U128(0),
+            refunded: false,
+            storage_deposit: U128(0),
+            revealed_deposit: U128(0),
+            protocol_fee_bps: 0,
+            integrator_fee: None,
+            sender: self.sender,
+            amount: self.amount,
+            timestamp: self.timestamp,
+            claimed: self.claimed,
+        }
+    }
+}
+
+/// Convierte en su sitio los depósitos de V1 y devuelve el mapa con el tipo de
+/// valor actual junto con los depósitos convertidos.
+///
+/// V1 guardaba igual el NEAR de `recive_near` y los importes de cualquier
+/// contrato que llamara a `ft_on_transfer`, así que el activo de cada depósito
+/// sin reclamar lo indica `assets`. Si falta alguno, o sobra alguno que no es
+/// de un depósito de V1, se entra en pánico. Los ya reclamados no se pagan y
+/// pueden faltar.
+///
+/// La estructura del mapa (prefijos y longitudes) no depende del tipo de valor,
+/// así que basta con reinterpretarla. Los valores se escriben en crudo porque
+/// `insert` intentaría leer el valor anterior como `VersionedDepositInfo`.
+pub fn upgrade_deposits(
+    old: &UnorderedMap<String, DepositInfoV1>,
+    assets: Vec<(String, Asset)>,
+) -> (UnorderedMap<String, VersionedDepositInfo>, Vec<(String, DepositInfo)>) {
+    let bytes = borsh::to_vec(old).unwrap_or_else(|_| env::abort());
+    let mut deposits = UnorderedMap::<String, VersionedDepositInfo>::try_from_slice(&bytes).unwrap_or_else(|_| env::abort());
+
+    let mut assets: HashMap<String, Asset> = assets.into_iter().collect();
+    let upgraded: Vec<(String, DepositInfo)> = old
+        .iter()
+        .map(|(key, deposit)| {
+            let asset = match assets.remove(&key) {
+                Some(asset) => asset,
+                None if deposit.claimed => Asset::Near,
+                None => env::panic_str("Falta el activo de un depósito de V1"),
+            };
+            (key.clone(), deposit.upgrade(&key, asset))
+        })
+        .collect();
+    require!(assets.is_empty(), "Hay activos de depósitos que no existen en V1");
+    for (key, deposit) in &upgraded {
+        let key_raw = borsh::to_vec(key).unwrap_or_else(|_| env::abort());
+        let value_raw = borsh::to_vec(&VersionedDepositInfo::from(deposit.clone())).unwrap_or_else(|_| env::abort());
+        deposits.insert_raw(&key_raw, &value_raw);
+    }
+
+    (deposits, upgraded)
+}
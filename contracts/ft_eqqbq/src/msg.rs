@@ -0,0 +1,119 @@
+use near_sdk::json_types::U128;
+use near_sdk::{near, AccountId};
+
+use crate::evm::{ChainId, EvmAddress};
+use crate::fees::IntegratorFee;
+use crate::hashlock::{normalize_bytes32, HashAlgorithm};
+use crate::timelocks::Timelocks;
+
+/// Datos para crear un depósito. En `ft_on_transfer` llegan como JSON en `msg`
+/// y en `recive_near` como argumento. La versión va en el campo `"version"`:
+///
+/// `{"version": "1", "hashlock": "<hex>", "taker": "resolver.near", "order_hash": "<hex>",
+///   "src_chain_id": 397, "dst_chain_id": 1, "evm_maker": "0x<40 hex>",
+///   "dst_token": "0x<40 hex>", "dst_amount": "1000", ...}`
+#[near(serializers = [json])]
+#[derive(Clone, Debug)]
+#[serde(tag = "version")]
+pub enum EscrowMsg {
+    #[serde(rename = "1")]
+    V1(EscrowMsgV1),
+}
+
+#[near(serializers = [json])]
+#[derive(Clone, Debug)]
+pub struct EscrowMsgV1 {
+    /// hash(secreto) en hexadecimal
+    pub hashlock: String,
+    /// Por defecto sha256
+    pub hash_algorithm: Option<HashAlgorithm>,
+    /// Resolver con exclusividad en las ventanas privadas
+    pub taker: AccountId,
+    /// Quién recibe los fondos al reclamar; por defecto el taker
+    pub recipient: Option<AccountId>,
+    /// Por defecto los `default_timelocks` del contrato
+    pub timelocks: Option<Timelocks>,
+    /// Hash de la orden de Fusion+ en hexadecimal (32 bytes)
+    pub order_hash: String,
+    pub src_chain_id: ChainId,
+    pub dst_chain_id: ChainId,
+    /// Dirección del maker en la cadena EVM de la orden
+    pub evm_maker: EvmAddress,
+    /// Token que recibe el maker en la cadena de destino (`address(0)` si es el nativo)
+    pub dst_token: EvmAddress,
+    /// Importe que espera recibir el maker en la cadena de destino
+    pub dst_amount: U128,
+    /// Depósito de seguridad en yoctoNEAR, por defecto 0
+    pub safety_deposit: Option<U128>,
+    /// Si se indica, `hashlock` es la raíz Merkle de `parts + 1` secretos y la
+    /// orden se puede rellenar por partes
+    pub parts: Option<u16>,
+    /// Comisión del integrador que trajo la orden; se cobra al reclamar. El
+    /// integrador tiene que estar registrado para el activo (`register_integrator`)
+    #[serde(default)]
+    pub integrator_fee: Option<IntegratorFee>,
+}
+
+/// `EscrowMsg` ya validado y con los valores por defecto aplicados.
+pub struct EscrowParams {
+    pub hashlock: String,
+    pub hash_algorithm: HashAlgorithm,
+    pub taker: AccountId,
+    pub recipient: AccountId,
+    pub timelocks: Timelocks,
+    pub order_hash: String,
+    pub src_chain_id: ChainId,
+    pub dst_chain_id: ChainId,
+    pub evm_maker: EvmAddress,
+    pub dst_token: EvmAddress,
+    pub dst_amount: U128,
+    pub safety_deposit: U128,
+    pub parts: Option<u16>,
+    pub integrator_fee: Option<IntegratorFee>,
+}
+
+impl EscrowMsg {
+    /// Interpreta el `msg` de `ft_on_transfer` sin entrar en pánico.
+    pub fn parse(msg: &str) -> Result<Self, &'static str> {
+        near_sdk::serde_json::from_str(msg).map_err(|_| "msg no es un EscrowMsg válido")
+    }
+
+    pub fn into_params(self, default_timelocks: Timelocks) -> Result<EscrowParams, &'static str> {
+        match self {
+            EscrowMsg::V1(msg) => msg.into_params(default_timelocks),
+        }
+    }
+}
+
+impl EscrowMsgV1 {
+    fn into_params(self, default_timelocks: Timelocks) -> Result<EscrowParams, &'static str> {
+        let timelocks = self.timelocks.unwrap_or(default_timelocks);
+        timelocks.validate()?;
+        if self.parts == Some(0) {
+            return Err("Una orden por partes necesita al menos una parte");
+        }
+        if self.src_chain_id == self.dst_chain_id {
+            return Err("La cadena de origen y la de destino deben ser distintas");
+        }
+        if let Some(fee) = &self.integrator_fee {
+            fee.validate()?;
+        }
+
+        Ok(EscrowParams {
+            hashlock: normalize_bytes32(&self.hashlock).ok_or("El hashlock debe ser de 32 bytes en hexadecimal")?,
+            hash_algorithm: self.hash_algorithm.unwrap_or(HashAlgorithm::Sha256),
+            recipient: self.recipient.unwrap_or_else(|| self.taker.clone()),
+            taker: self.taker,
+            timelocks,
+            order_hash: normalize_bytes32(&self.order_hash).ok_or("El order_hash debe ser de 32 bytes en hexadecimal")?,
+            src_chain_id: self.src_chain_id,
+            dst_chain_id: self.dst_chain_id,
+            evm_maker: self.evm_maker,
+            dst_token: self.dst_token,
+            dst_amount: self.dst_amount,
+            safety_deposit: self.safety_deposit.unwrap_or(U128(0)),
+            parts: self.parts,
+            integrator_fee: self.integrator_fee,
+        })
+    }
+}
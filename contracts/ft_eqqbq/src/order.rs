@@ -0,0 +1,122 @@
+use near_sdk::borsh;
+use near_sdk::json_types::U128;
+use near_sdk::{env, near, AccountId};
+
+use crate::auction::AuctionDetails;
+use crate::evm::{ChainId, EvmAddress};
+use crate::hashlock::HashAlgorithm;
+use crate::msg::{EscrowMsg, EscrowMsgV1};
+use crate::timelocks::{Timelocks, NANOS_PER_SECOND};
+
+/// Bits de cada palabra del bitmap de nonces
+const NONCES_PER_SERIES: u64 = 128;
+
+/// Orden firmada por el maker fuera de la cadena. Se firma con ed25519 la
+/// serialización Borsh de la orden y cualquier resolver la puede rellenar con
+/// `fill_order`; el escrow sale del saldo de tokens del maker en el contrato.
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Order {
+    /// Contrato en el que vale la firma, para que no se pueda usar en otro despliegue
+    pub verifying_contract: AccountId,
+    pub maker: AccountId,
+    /// Cada nonce sólo se puede usar una vez por maker
+    pub nonce: u64,
+    /// Época del maker al firmar; `increase_epoch` invalida las anteriores
+    pub epoch: u64,
+    /// Segundos desde epoch a partir de los que ya no se puede rellenar
+    pub expiration: u64,
+    /// Token NEP-141 que vende el maker
+    pub token: AccountId,
+    pub amount: U128,
+    /// hash(secreto) en hexadecimal; el secreto lo guarda el maker
+    pub hashlock: String,
+    pub hash_algorithm: HashAlgorithm,
+    /// Por defecto los `default_timelocks` del contrato
+    pub timelocks: Option<Timelocks>,
+    /// Depósito de seguridad en yoctoNEAR; lo adjunta el resolver al rellenar la orden
+    pub safety_deposit: U128,
+    pub src_chain_id: ChainId,
+    pub dst_chain_id: ChainId,
+    pub evm_maker: EvmAddress,
+    pub dst_token: EvmAddress,
+    /// Importe mínimo en destino; con subasta es el final de la curva
+    pub dst_amount: U128,
+    /// Subasta holandesa del importe en destino; sin ella vale `dst_amount`
+    #[serde(default)]
+    pub auction: Option<AuctionDetails>,
+}
+
+impl Order {
+    /// Bytes que firma el maker.
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        borsh::to_vec(self).unwrap_or_else(|_| env::abort())
+    }
+
+    /// `keccak256` de la orden firmada, en hexadecimal. Es el `order_hash` del escrow.
+    pub fn hash(&self) -> String {
+        hex::encode(env::keccak256_array(&self.signed_bytes()))
+    }
+
+    pub fn verify_signature(&self, signature: &[u8], public_key: &[u8; 32]) -> Result<(), &'static str> {
+        let signature: &[u8; 64] = signature.try_into().map_err(|_| "La firma debe ser de 64 bytes")?;
+        if !env::ed25519_verify(signature, &self.signed_bytes(), public_key) {
+            return Err("La firma de la orden no es válida");
+        }
+        Ok(())
+    }
+
+    /// Importe en destino que tiene que ofrecer como mínimo el taker ahora.
+    pub fn current_dst_amount(&self) -> Result<u128, &'static str> {
+        match &self.auction {
+            Some(auction) => {
+                auction.validate(self.dst_amount.0)?;
+                Ok(auction.current_amount(self.dst_amount.0))
+            }
+            None => Ok(self.dst_amount.0),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        env::block_timestamp() >= self.expiration.saturating_mul(NANOS_PER_SECOND)
+    }
+
+    /// Datos del escrow que crea `taker` al rellenar la orden por `dst_amount`.
+    /// Los fondos van al taker al reclamar, como en el escrow de origen de Fusion+.
+    pub fn escrow_msg(&self, taker: AccountId, dst_amount: U128) -> EscrowMsg {
+        EscrowMsg::V1(EscrowMsgV1 {
+            hashlock: self.hashlock.clone(),
+            hash_algorithm: Some(self.hash_algorithm),
+            taker,
+            recipient: None,
+            timelocks: self.timelocks,
+            order_hash: self.hash(),
+            src_chain_id: self.src_chain_id,
+            dst_chain_id: self.dst_chain_id,
+            evm_maker: self.evm_maker,
+            dst_token: self.dst_token,
+            dst_amount,
+            safety_deposit: Some(self.safety_deposit),
+            parts: None,
+            integrator_fee: None,
+        })
+    }
+}
+
+/// Serie y bit de un nonce en el bitmap de nonces usados.
+pub fn nonce_slot(nonce: u64) -> (u64, u128) {
+    (nonce / NONCES_PER_SERIES, 1 << (nonce % NONCES_PER_SERIES))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nonce_slots_pack_128_nonces_per_series() {
+        assert_eq!(nonce_slot(0), (0, 1));
+        assert_eq!(nonce_slot(127), (0, 1 << 127));
+        assert_eq!(nonce_slot(128), (1, 1));
+        assert_eq!(nonce_slot(u64::MAX), (u64::MAX / 128, 1 << 127));
+    }
+}
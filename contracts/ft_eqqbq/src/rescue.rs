@@ -0,0 +1,36 @@
+use near_sdk::json_types::U128;
+use near_sdk::near;
+
+use crate::timelocks::NANOS_PER_SECOND;
+
+/// Rescate propuesto por el owner para un activo.
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RescueProposal {
+    pub amount: U128,
+    /// Momento (nanosegundos) de `propose_rescue`; de aquí se cuenta `rescue_delay`
+    pub proposed_at: u64,
+    /// Se está ejecutando: hay una consulta de saldo o una transferencia en curso
+    pub executing: bool,
+}
+
+impl RescueProposal {
+    /// Si ya han pasado `rescue_delay` segundos desde que se propuso.
+    pub fn is_ready(&self, rescue_delay: u32, now: u64) -> bool {
+        now >= self.proposed_at + u64::from(rescue_delay) * NANOS_PER_SECOND
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ready_once_the_delay_has_passed() {
+        let proposal = RescueProposal { amount: U128(5), proposed_at: 10, executing: false };
+
+        assert!(!proposal.is_ready(1, 10 + NANOS_PER_SECOND - 1));
+        assert!(proposal.is_ready(1, 10 + NANOS_PER_SECOND));
+        assert!(proposal.is_ready(0, 10));
+    }
+}
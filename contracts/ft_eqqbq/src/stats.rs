@@ -0,0 +1,31 @@
+use near_sdk::json_types::U128;
+use near_sdk::near;
+
+use crate::asset::Asset;
+
+/// Contadores de escrows. `total_created` sube al crear el depósito; los otros
+/// dos cuando se confirma el pago de la retirada o de la devolución.
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub total_created: u64,
+    pub total_claimed: u64,
+    pub total_refunded: u64,
+}
+
+/// Importe bloqueado en escrows activos de un activo.
+#[near(serializers = [json])]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LockedValue {
+    pub asset: Asset,
+    pub amount: U128,
+}
+
+/// Respuesta de `get_stats`.
+#[near(serializers = [json])]
+#[derive(Clone, Debug)]
+pub struct StatsView {
+    #[serde(flatten)]
+    pub stats: Stats,
+    pub locked: Vec<LockedValue>,
+}
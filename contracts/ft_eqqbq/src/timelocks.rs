@@ -0,0 +1,83 @@
+use near_sdk::{env, near};
+
+pub const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Calendario de un depósito al estilo de Fusion+. Cada valor son los segundos
+/// desde la creación del depósito hasta que empieza la etapa.
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timelocks {
+    /// Inicio de la retirada exclusiva del taker (resolver)
+    pub withdrawal: u32,
+    /// Inicio de la retirada pública: cualquiera con el secreto
+    pub public_withdrawal: u32,
+    /// Inicio de la cancelación exclusiva del taker
+    pub cancellation: u32,
+    /// Inicio de la cancelación pública: cualquiera puede devolver los fondos
+    pub public_cancellation: u32,
+}
+
+/// Etapa en la que se encuentra un depósito según sus `Timelocks`.
+#[near(serializers = [json])]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Todavía no se puede retirar ni cancelar
+    Locked,
+    Withdrawal,
+    PublicWithdrawal,
+    Cancellation,
+    PublicCancellation,
+}
+
+impl Timelocks {
+    /// Reclamar desde el inicio y devolver tras 24 horas, como hacía el contrato original.
+    pub const DEFAULT: Timelocks = Timelocks {
+        withdrawal: 0,
+        public_withdrawal: 0,
+        cancellation: 60 * 60 * 24,
+        public_cancellation: 60 * 60 * 24,
+    };
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.withdrawal <= self.public_withdrawal
+            && self.public_withdrawal < self.cancellation
+            && self.cancellation <= self.public_cancellation
+        {
+            Ok(())
+        } else {
+            Err("Los timelocks deben ser crecientes y la cancelación posterior a la retirada")
+        }
+    }
+
+    /// Etapa en `now` (nanosegundos) de un depósito creado en `deployed_at` (nanosegundos).
+    pub fn stage(&self, deployed_at: u64, now: u64) -> Stage {
+        let elapsed = now.saturating_sub(deployed_at) / NANOS_PER_SECOND;
+        if elapsed >= u64::from(self.public_cancellation) {
+            Stage::PublicCancellation
+        } else if elapsed >= u64::from(self.cancellation) {
+            Stage::Cancellation
+        } else if elapsed >= u64::from(self.public_withdrawal) {
+            Stage::PublicWithdrawal
+        } else if elapsed >= u64::from(self.withdrawal) {
+            Stage::Withdrawal
+        } else {
+            Stage::Locked
+        }
+    }
+
+    pub fn current_stage(&self, deployed_at: u64) -> Stage {
+        self.stage(deployed_at, env::block_timestamp())
+    }
+
+    /// Momento (nanosegundos) en que empieza la cancelación.
+    pub fn cancellation_start(&self, deployed_at: u64) -> u64 {
+        deployed_at + u64::from(self.cancellation) * NANOS_PER_SECOND
+    }
+}
+
+impl Default for Timelocks {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
@@ -0,0 +1,68 @@
+use near_sdk::json_types::U128;
+use near_sdk::{near, AccountId};
+
+/// Importes admitidos para los escrows de un token NEP-141.
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TokenLimits {
+    /// Por defecto 0
+    #[serde(default)]
+    pub min_amount: U128,
+    /// Sin límite si no se indica
+    #[serde(default)]
+    pub max_amount: Option<U128>,
+}
+
+/// Token de la allowlist con sus límites.
+#[near(serializers = [json])]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SupportedToken {
+    pub token_id: AccountId,
+    #[serde(flatten)]
+    pub limits: TokenLimits,
+}
+
+impl TokenLimits {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        match self.max_amount {
+            Some(max) if max.0 < self.min_amount.0 => Err("El máximo del token debe ser mayor o igual que el mínimo"),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check(&self, amount: U128) -> Result<(), &'static str> {
+        if amount.0 < self.min_amount.0 {
+            return Err("El importe está por debajo del mínimo del token");
+        }
+        match self.max_amount {
+            Some(max) if amount.0 > max.0 => Err("El importe supera el máximo del token"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_bounds_are_inclusive() {
+        let limits = TokenLimits { min_amount: U128(10), max_amount: Some(U128(20)) };
+
+        assert!(limits.check(U128(9)).is_err());
+        assert!(limits.check(U128(10)).is_ok());
+        assert!(limits.check(U128(20)).is_ok());
+        assert!(limits.check(U128(21)).is_err());
+    }
+
+    #[test]
+    fn default_limits_accept_any_amount() {
+        assert!(TokenLimits::default().check(U128(u128::MAX)).is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_max_below_min() {
+        assert!(TokenLimits { min_amount: U128(10), max_amount: Some(U128(9)) }.validate().is_err());
+        assert!(TokenLimits { min_amount: U128(10), max_amount: None }.validate().is_ok());
+    }
+}
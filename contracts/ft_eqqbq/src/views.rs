@@ -0,0 +1,72 @@
+use near_sdk::collections::UnorderedSet;
+use near_sdk::json_types::U128;
+use near_sdk::{near, AccountId};
+
+use crate::timelocks::Timelocks;
+use crate::tokens::SupportedToken;
+use crate::DepositInfo;
+
+/// Estado de un depósito derivado de sus flags y de la etapa actual.
+#[near(serializers = [json])]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EscrowStatus {
+    /// Todavía no se puede reclamar ni cancelar
+    Active,
+    /// En la ventana de retirada
+    Claimable,
+    /// En la ventana de cancelación
+    Refundable,
+    Claimed,
+    Refunded,
+}
+
+/// Depósito devuelto por las vistas paginadas.
+#[near(serializers = [json])]
+pub struct DepositView {
+    pub escrow_id: String,
+    pub status: EscrowStatus,
+    #[serde(flatten)]
+    pub deposit: DepositInfo,
+}
+
+/// Página de `get_deposits`.
+#[near(serializers = [json])]
+pub struct DepositsPage {
+    pub deposits: Vec<DepositView>,
+    /// Posición por la que seguir en la siguiente llamada; `None` al llegar al final
+    pub next_index: Option<U128>,
+}
+
+/// Configuración del contrato.
+#[near(serializers = [json])]
+pub struct ConfigView {
+    pub owner_id: AccountId,
+    pub pending_owner_id: Option<AccountId>,
+    pub paused: bool,
+    pub default_timelocks: Timelocks,
+    pub supported_tokens: Vec<SupportedToken>,
+    /// Segundos desde `propose_rescue` hasta que se puede llamar a `execute_rescue`
+    pub rescue_delay: u32,
+    /// Comisión del protocolo en puntos básicos para los escrows nuevos
+    pub protocol_fee_bps: u16,
+}
+
+/// Elementos de `from_index` a `from_index + limit`, como en las vistas
+/// paginadas de `near_contract_standards`.
+pub fn paginate<T>(iter: impl Iterator<Item = T>, from_index: Option<U128>, limit: Option<u64>) -> impl Iterator<Item = T> {
+    let from_index = from_index.map_or(0, |index| index.0);
+    let limit = limit.unwrap_or(u64::MAX);
+    near_sdk::require!(limit != 0, "El límite no puede ser 0");
+
+    iter.skip(usize::try_from(from_index).unwrap_or(usize::MAX))
+        .take(usize::try_from(limit).unwrap_or(usize::MAX))
+}
+
+/// escrow_ids de un índice secundario, paginados.
+pub fn paginate_set(set: Option<UnorderedSet<String>>, from_index: Option<U128>, limit: Option<u64>) -> Vec<String> {
+    match set {
+        Some(set) => paginate(set.iter(), from_index, limit).collect(),
+        None => Vec::new(),
+    }
+}
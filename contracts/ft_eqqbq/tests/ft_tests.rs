@@ -1,5 +1,3 @@
-use near_sdk::json_types::U128;
-use near_workspaces::{types::NearToken, Account, Contract};
 use serde_json::json;
 
 #[tokio::test]
@@ -13,7 +11,8 @@ async fn test_ft_total_supply() -> Result<(), Box<dyn std::error::Error>> {
     let outcome = contract
         .call("init")
         .args_json(json!({
-            "deposit_number": "3",
+            "owner_id": contract.id(),
+            "supported_tokens": [],
         }))
         .transact()
         .await?;
@@ -24,8 +23,8 @@ async fn test_ft_total_supply() -> Result<(), Box<dyn std::error::Error>> {
     // Check total supply
     //let total_supply: String = contract.view("ft_total_supply").await?.json()?;
 
-    let deposit_number: String = contract.view("get_deposit_number").await?.json()?;
-    assert_eq!(deposit_number, "3");
+    let stats: serde_json::Value = contract.view("get_stats").await?.json()?;
+    assert_eq!(stats["total_created"], 0);
 
     Ok(())
 }